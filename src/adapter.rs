@@ -2,87 +2,708 @@
 
 use {
     crate::{
-        servant::{Record, ServantRegister, ServantResult},
-        sync::{Arc, Mutex},
+        compress::{Compression, CompressionStats},
+        config,
+        durable::DurableNotices,
+        format::Format,
+        handshake,
+        queue::{OverflowPolicy, QueueStats, RecordQueue},
+        servant::{
+            CancelHandle, ConnectionId, Context, Oid, Record, ServantError, ServantRegister,
+            ServantResult,
+        },
+        transport::{Connection, FrameSettings, RecordReader, RecordWriter},
+        sync::{Arc, Condvar, Mutex},
         task,
-        utilities::DropGuard,
+        utilities::{BoolToOption, DropGuard},
     },
-    async_std::{net::TcpStream, prelude::*},
-    codec::RecordCodec,
+    async_std::{future, prelude::*, stream},
     futures::{
         channel::mpsc::{unbounded, UnboundedSender},
+        future::join_all,
         pin_mut, select,
         sink::SinkExt,
         FutureExt as _,
     },
-    futures_codec::{FramedRead, FramedWrite},
     log::{info, warn},
-    std::{collections::HashMap, net::SocketAddr},
+    std::{
+        collections::{HashMap, HashSet, VecDeque},
+        pin::Pin,
+        sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+        time::{Duration, Instant},
+    },
 };
 
 // --
 
+/// decides whether a terminal may connect, by the auth data in its hello.
+pub type Authenticator = Arc<dyn Fn(&ConnectionId, Option<&[u8]>) -> bool + Send + Sync>;
+/// decides whether a terminal may resume the identity, by the auth data in its hello.
+pub type IdentityAuthenticator =
+    Arc<dyn Fn(&ConnectionId, Option<&[u8]>, &str) -> bool + Send + Sync>;
+
+// the category of the persisted durable notices, the name is the identity.
+const DURABLE_NOTICE_CATEGORY: &str = "DurableNotice";
+// the count of reliable report ids remembered for each terminal.
+const REPORT_WINDOW: usize = 1024;
+// the count of terminals whose reliable reports are remembered.
+const REPORT_WINDOWS: usize = 1024;
+
+// the reliable reports served lately and their results, none while being served.
+struct _ReportWindow {
+    results: HashMap<usize, Option<ServantResult<()>>>,
+    order: VecDeque<usize>,
+    used: Instant,
+}
+impl _ReportWindow {
+    fn new() -> Self {
+        Self {
+            results: HashMap::new(),
+            order: VecDeque::new(),
+            used: Instant::now(),
+        }
+    }
+    // the entry of the id, if it's seen already. otherwise it's marked as being served.
+    fn seen(&mut self, id: usize) -> Option<Option<ServantResult<()>>> {
+        self.used = Instant::now();
+        if let Some(ret) = self.results.get(&id) {
+            return Some(ret.clone());
+        }
+        self.results.insert(id, None);
+        self.order.push_back(id);
+        if self.order.len() > REPORT_WINDOW {
+            if let Some(oldest) = self.order.pop_front() {
+                self.results.remove(&oldest);
+            }
+        }
+        None
+    }
+}
+
 struct _Register {
     id: usize,
-    accept_tx: Option<UnboundedSender<()>>,
-    senders: HashMap<SocketAddr, UnboundedSender<Record>>,
+    accept_txs: Vec<UnboundedSender<()>>,
+    senders: HashMap<ConnectionId, RecordQueue>,
+    settings: HashMap<ConnectionId, Arc<FrameSettings>>,
+    groups: HashMap<String, HashSet<ConnectionId>>,
+    subscriptions: HashMap<String, HashSet<ConnectionId>>,
+    shutdown_deadline_ms: u64,
+    handshake_timeout_ms: u64,
+    heartbeat_interval_ms: u64,
+    idle_timeout_ms: u64,
+    upload_credit: usize,
+    queue_capacity: usize,
+    overflow_policy: OverflowPolicy,
+    limits: config::LimitData,
+    compression_threshold: usize,
+    durable: DurableNotices,
+    store: Option<ServantRegister>,
+    // by the nonce of the terminal, which outlives its connections.
+    reports: HashMap<u64, _ReportWindow>,
+    authenticator: Option<Authenticator>,
+    identity_authenticator: Option<IdentityAuthenticator>,
+    // the auth data in the hello of every connection accepted.
+    auths: HashMap<ConnectionId, Option<Vec<u8>>>,
+    closing: bool,
+    closed: bool,
+    in_flight: usize,
+    // the connections accepted and still in the handshake, they count as connected.
+    connecting: usize,
+}
+impl _Register {
+    // the buffers of the identities to be persisted, none if it's off.
+    fn dumps(&self, identities: Vec<String>) -> Vec<(Oid, Vec<u8>)> {
+        if self.store.is_none() {
+            return Vec::new();
+        }
+        identities
+            .iter()
+            .filter_map(|i| {
+                self.durable
+                    .dump(i)
+                    .map(|bytes| (Oid::new(i, DURABLE_NOTICE_CATEGORY), bytes))
+            })
+            .collect()
+    }
 }
 
+// the topic, if any, is the one `send` publishes on.
 #[derive(Clone)]
-pub struct AdapterRegister(Arc<Mutex<_Register>>);
+pub struct AdapterRegister(Arc<Mutex<_Register>>, Option<String>);
 impl AdapterRegister {
-    pub(crate) fn new() -> Self {
-        Self(Arc::new(Mutex::new(_Register {
+    pub(crate) fn new(config: &config::Server, sr: &ServantRegister) -> Self {
+        let register = _Register {
             id: 0,
-            accept_tx: None,
+            accept_txs: Vec::new(),
             senders: HashMap::new(),
-        })))
+            settings: HashMap::new(),
+            groups: HashMap::new(),
+            subscriptions: HashMap::new(),
+            shutdown_deadline_ms: config.shutdown_deadline_millisecond,
+            handshake_timeout_ms: config.handshake_timeout_millisecond,
+            heartbeat_interval_ms: config.heartbeat_interval_millisecond,
+            idle_timeout_ms: config.idle_timeout_millisecond,
+            upload_credit: config.upload_credit_by_adapter.max(1),
+            queue_capacity: config.queue_capacity_by_adapter,
+            overflow_policy: config.overflow_policy,
+            limits: config.limits.clone(),
+            compression_threshold: config.compression_threshold,
+            durable: DurableNotices::new(&config.durable_notice),
+            store: config.durable_notice.persist.then_some2(sr.clone()),
+            reports: HashMap::new(),
+            authenticator: None,
+            identity_authenticator: None,
+            auths: HashMap::new(),
+            closing: false,
+            closed: false,
+            in_flight: 0,
+            connecting: 0,
+        };
+        Self(Arc::new(Mutex::new(register)), None)
     }
     pub async fn clean(&self) {
         let mut g = self.0.lock().await;
-        g.accept_tx.take();
+        g.accept_txs.clear();
         g.senders.clear();
+        g.settings.clear();
+        g.groups.clear();
+        g.subscriptions.clear();
+        g.auths.clear();
+        g.closed = true;
+    }
+    pub async fn shutdown(&self) {
+        let deadline = {
+            let mut g = self.0.lock().await;
+            if g.closing {
+                return;
+            }
+            g.closing = true;
+            let record = Record::Shutdown {
+                deadline_millisecond: g.shutdown_deadline_ms,
+            };
+            g.senders.values().for_each(|s| {
+                s.force(record.clone())
+                    .unwrap_or_else(|e| warn!("{}", e.to_string()))
+            });
+            Instant::now() + Duration::from_millis(g.shutdown_deadline_ms)
+        };
+        info!("server is shutting down, stop accepting.");
+        loop {
+            let in_flight = self.0.lock().await.in_flight;
+            if in_flight == 0 {
+                break;
+            }
+            if Instant::now() >= deadline {
+                warn!("{} requests are still in flight at shutdown deadline.", in_flight);
+                break;
+            }
+            task::sleep(Duration::from_millis(10)).await;
+        }
+        let mut g = self.0.lock().await;
+        g.senders.values().for_each(|s| s.close());
+        g.senders.clear();
+        g.settings.clear();
+        g.groups.clear();
+        g.subscriptions.clear();
+        g.auths.clear();
+        g.accept_txs.clear();
+        g.closed = true;
+        info!("server is shut down.");
+    }
+    pub async fn set_authenticator(&self, authenticator: Authenticator) {
+        let mut g = self.0.lock().await;
+        g.authenticator.replace(authenticator);
+    }
+    /// without it, no terminal may resume an identity.
+    pub async fn set_identity_authenticator(&self, authenticator: IdentityAuthenticator) {
+        let mut g = self.0.lock().await;
+        g.identity_authenticator.replace(authenticator);
+    }
+    async fn authenticate(&self, addr: &ConnectionId, auth: Option<&[u8]>) -> bool {
+        let mut g = self.0.lock().await;
+        let accepted = match g.authenticator.as_ref() {
+            Some(f) => f(addr, auth),
+            None => true,
+        };
+        if accepted {
+            g.auths.insert(addr.clone(), auth.map(|a| a.to_vec()));
+        }
+        accepted
+    }
+    async fn handshake_timeout_ms(&self) -> u64 {
+        let g = self.0.lock().await;
+        g.handshake_timeout_ms
+    }
+    async fn heartbeat(&self) -> (u64, u64) {
+        let g = self.0.lock().await;
+        (g.heartbeat_interval_ms, g.idle_timeout_ms)
+    }
+    async fn upload_credit(&self) -> usize {
+        let g = self.0.lock().await;
+        g.upload_credit
+    }
+    async fn limits(&self) -> config::LimitData {
+        let g = self.0.lock().await;
+        g.limits.clone()
+    }
+    async fn compression_threshold(&self) -> usize {
+        let g = self.0.lock().await;
+        g.compression_threshold
+    }
+    /// the payload bytes before and after compression on every connection.
+    pub async fn compression_stats(&self) -> Vec<(ConnectionId, CompressionStats)> {
+        let g = self.0.lock().await;
+        g.settings
+            .iter()
+            .map(|(addr, s)| (addr.clone(), s.compression_stats()))
+            .collect()
+    }
+    async fn new_queue(&self) -> RecordQueue {
+        let g = self.0.lock().await;
+        RecordQueue::new(g.queue_capacity, g.overflow_policy)
+    }
+    /// changes the overflow policy of the queue to one connection.
+    pub async fn set_overflow_policy(&self, addr: &ConnectionId, policy: OverflowPolicy) -> bool {
+        let g = self.0.lock().await;
+        match g.senders.get(addr) {
+            Some(s) => {
+                s.set_policy(policy);
+                true
+            }
+            None => false,
+        }
     }
-    pub(crate) async fn count(&self) -> usize {
+    /// the depth of the queue to every connection.
+    pub async fn queue_stats(&self) -> Vec<(ConnectionId, QueueStats)> {
         let g = self.0.lock().await;
-        g.senders.len()
+        g.senders
+            .iter()
+            .map(|(addr, s)| (addr.clone(), s.stats()))
+            .collect()
     }
-    pub(crate) async fn set_accept(&self, tx: UnboundedSender<()>) {
+    pub(crate) async fn is_closing(&self) -> bool {
+        let g = self.0.lock().await;
+        g.closing
+    }
+    pub(crate) async fn wait_closed(&self) {
+        loop {
+            {
+                let g = self.0.lock().await;
+                if !g.closing || g.closed {
+                    break;
+                }
+            }
+            task::sleep(Duration::from_millis(10)).await;
+        }
+    }
+    async fn begin_serve(&self) {
+        let mut g = self.0.lock().await;
+        g.in_flight += 1;
+    }
+    async fn end_serve(&self) {
+        let mut g = self.0.lock().await;
+        g.in_flight -= 1;
+    }
+    // a slot for the connection accepted, the adapter releases it after the handshake.
+    pub(crate) async fn reserve(&self, max_count: usize) -> bool {
+        let mut g = self.0.lock().await;
+        if g.senders.len() + g.connecting >= max_count {
+            false
+        } else {
+            g.connecting += 1;
+            true
+        }
+    }
+    async fn release(&self) {
         let mut g = self.0.lock().await;
-        g.accept_tx = Some(tx);
+        g.connecting = g.connecting.saturating_sub(1);
     }
-    pub(crate) async fn insert(&self, addr: SocketAddr, tx: UnboundedSender<Record>) {
+    pub(crate) async fn add_accept(&self, tx: UnboundedSender<()>) {
         let mut g = self.0.lock().await;
+        g.accept_txs.push(tx);
+    }
+    pub(crate) async fn insert(
+        &self,
+        addr: ConnectionId,
+        tx: RecordQueue,
+        settings: Arc<FrameSettings>,
+    ) {
+        let mut g = self.0.lock().await;
+        g.settings.insert(addr.clone(), settings);
         g.senders.insert(addr, tx);
     }
-    pub(crate) async fn remove(&self, addr: &SocketAddr) {
+    pub(crate) async fn remove(&self, addr: &ConnectionId) {
         let mut g = self.0.lock().await;
-        g.senders.remove(addr);
+        g.settings.remove(addr);
+        g.auths.remove(addr);
+        g.durable.unbind(addr);
+        g.groups.values_mut().for_each(|members| {
+            members.remove(addr);
+        });
+        g.groups.retain(|_, members| !members.is_empty());
+        g.subscriptions.values_mut().for_each(|members| {
+            members.remove(addr);
+        });
+        g.subscriptions.retain(|_, members| !members.is_empty());
+        // the tasks waiting for room in the queue give up.
+        if let Some(s) = g.senders.remove(addr) {
+            s.close();
+        }
     }
-    pub(crate) async fn list(&self) -> Vec<SocketAddr> {
+    pub(crate) async fn list(&self) -> Vec<ConnectionId> {
         let g = self.0.lock().await;
         g.senders.iter().map(|v| v.0.clone()).collect()
     }
-    pub async fn send(&self, msg: Vec<u8>) {
+    /// adds the connection to the group, false if it isn't connected.
+    /// the connection leaves all its groups when it's closed.
+    pub async fn join(&self, group: &str, addr: &ConnectionId) -> bool {
+        let mut g = self.0.lock().await;
+        if !g.senders.contains_key(addr) {
+            return false;
+        }
+        g.groups
+            .entry(group.to_string())
+            .or_insert_with(HashSet::new)
+            .insert(addr.clone());
+        true
+    }
+    pub async fn leave(&self, group: &str, addr: &ConnectionId) {
         let mut g = self.0.lock().await;
-        g.id += 1;
-        let notice = Record::Notice { id: g.id, msg };
-        let mut values = g.senders.values();
-        while let Some(mut s) = values.next() {
-            s.send(notice.clone())
+        if let Some(members) = g.groups.get_mut(group) {
+            members.remove(addr);
+            if members.is_empty() {
+                g.groups.remove(group);
+            }
+        }
+    }
+    pub async fn members(&self, group: &str) -> Vec<ConnectionId> {
+        let g = self.0.lock().await;
+        g.groups
+            .get(group)
+            .map(|members| members.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+    /// a register whose `send` publishes on the topic, for the notifiers which
+    /// only know `send`.
+    pub fn with_topic(&self, topic: &str) -> Self {
+        Self(self.0.clone(), Some(topic.to_string()))
+    }
+    pub(crate) async fn subscribe(&self, topic: String, addr: &ConnectionId) {
+        let mut g = self.0.lock().await;
+        if g.senders.contains_key(addr) {
+            g.subscriptions
+                .entry(topic)
+                .or_insert_with(HashSet::new)
+                .insert(addr.clone());
+        }
+    }
+    pub(crate) async fn unsubscribe(&self, topic: &str, addr: &ConnectionId) {
+        let mut g = self.0.lock().await;
+        if let Some(members) = g.subscriptions.get_mut(topic) {
+            members.remove(addr);
+            if members.is_empty() {
+                g.subscriptions.remove(topic);
+            }
+        }
+    }
+    pub async fn subscribers(&self, topic: &str) -> Vec<ConnectionId> {
+        let g = self.0.lock().await;
+        g.subscriptions
+            .get(topic)
+            .map(|members| members.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+    /// sends the notice to every connection, or publishes it on the topic
+    /// of this register.
+    pub async fn send(&self, msg: Vec<u8>) {
+        match self.1.as_ref() {
+            Some(topic) => self.notify(Target::Topic(topic), msg).await,
+            None => self.notify(Target::All, msg).await,
+        };
+    }
+    /// sends the notice to the subscribers of the topic, returns the count sent to.
+    pub async fn publish(&self, topic: &str, msg: Vec<u8>) -> usize {
+        self.notify(Target::Topic(topic), msg).await
+    }
+    /// sends the notice to one connection, false if it isn't connected.
+    pub async fn send_to(&self, addr: &ConnectionId, msg: Vec<u8>) -> bool {
+        self.notify(Target::List(std::slice::from_ref(addr)), msg)
+            .await
+            > 0
+    }
+    /// sends the notice to the connections in the list, returns the count sent to.
+    pub async fn send_to_list(&self, addrs: &[ConnectionId], msg: Vec<u8>) -> usize {
+        self.notify(Target::List(addrs), msg).await
+    }
+    /// sends the notice to the members of the group, returns the count sent to.
+    pub async fn send_to_group(&self, group: &str, msg: Vec<u8>) -> usize {
+        self.notify(Target::Group(group), msg).await
+    }
+    async fn notify(&self, target: Target<'_>, msg: Vec<u8>) -> usize {
+        let (notice, senders, dumps) = {
+            let mut g = self.0.lock().await;
+            if let Err(e) = check_size("notice", msg.len(), g.limits.max_notice_size) {
+                warn!("notice isn't sent: {}", e);
+                return 0;
+            }
+            let mut senders: Vec<_> = match target {
+                Target::All => g
+                    .senders
+                    .iter()
+                    .map(|(a, s)| (a.clone(), s.clone()))
+                    .collect(),
+                Target::List(addrs) => addrs
+                    .iter()
+                    .filter_map(|a| g.senders.get(a).map(|s| (a.clone(), s.clone())))
+                    .collect(),
+                Target::Group(group) => match g.groups.get(group) {
+                    Some(members) => members
+                        .iter()
+                        .filter_map(|a| g.senders.get(a).map(|s| (a.clone(), s.clone())))
+                        .collect(),
+                    None => Vec::new(),
+                },
+                Target::Topic(topic) => match g.subscriptions.get(topic) {
+                    Some(members) => members
+                        .iter()
+                        .filter_map(|a| g.senders.get(a).map(|s| (a.clone(), s.clone())))
+                        .collect(),
+                    None => Vec::new(),
+                },
+            };
+            g.id += 1;
+            let notice = match target {
+                Target::Topic(topic) => Record::TopicNotice {
+                    id: g.id,
+                    topic: topic.to_string(),
+                    msg,
+                },
+                _ => Record::Notice { id: g.id, msg },
+            };
+            let mut dumps = Vec::new();
+            if let Target::All = target {
+                let id = g.id;
+                let identities = g.durable.push(id, &notice);
+                dumps = g.dumps(identities);
+                // the replay sends it from the buffer, so it's never before the older ones.
+                let replaying = g.durable.replaying();
+                senders.retain(|(addr, _)| !replaying.contains(addr));
+            }
+            (notice, senders, dumps)
+        };
+        self.persist(dumps).await;
+        // a blocked queue holds up neither the register nor the other connections.
+        let sent = join_all(senders.into_iter().map(|(addr, s)| {
+            let notice = notice.clone();
+            async move {
+                match s.push(notice).await {
+                    Ok(_) => true,
+                    Err(e) => {
+                        warn!("notice to {}: {}", addr, e.to_string());
+                        false
+                    }
+                }
+            }
+        }))
+        .await;
+        sent.into_iter().filter(|x| *x).count()
+    }
+    /// binds the identity to the connection, and replays the notices after `last_id`.
+    pub(crate) async fn resume(&self, identity: String, addr: &ConnectionId, last_id: usize) {
+        let store = {
+            let g = self.0.lock().await;
+            let auth = g.auths.get(addr).and_then(|a| a.as_deref());
+            let allowed = match g.identity_authenticator.as_ref() {
+                Some(f) => f(addr, auth, &identity),
+                None => false,
+            };
+            if !allowed {
+                warn!("{} isn't allowed to resume {}.", addr, &identity);
+                return;
+            }
+            (!g.durable.contains(&identity))
+                .then2(|| g.store.clone())
+                .flatten()
+        };
+        let bytes = match store {
+            Some(s) => s
+                .load_bytes(&Oid::new(&identity, DURABLE_NOTICE_CATEGORY))
                 .await
-                .unwrap_or_else(|e| warn!("{}", e.to_string()));
+                .ok(),
+            None => None,
+        };
+        let dumps = {
+            let mut g = self.0.lock().await;
+            let tx = match g.senders.get(addr) {
+                Some(s) => s.clone(),
+                None => return,
+            };
+            if let Some(bytes) = bytes {
+                if !g.durable.contains(&identity) {
+                    g.durable.load(&identity, &bytes);
+                }
+            }
+            g.durable.resume(&identity, addr, last_id);
+            // after a restart the ids go on from where the terminal is.
+            g.id = g.id.max(last_id).max(g.durable.max_id(&identity));
+            task::spawn(self.clone().replay(identity.clone(), addr.clone(), tx, last_id));
+            g.dumps(vec![identity])
+        };
+        self.persist(dumps).await;
+    }
+    // the notices after `last_id` go through the queue in order, the new ones wait in the
+    // buffer till the replay catches up with them.
+    async fn replay(self, identity: String, addr: ConnectionId, tx: RecordQueue, last_id: usize) {
+        let mut last_id = last_id;
+        let mut count = 0;
+        loop {
+            let records = {
+                let mut g = self.0.lock().await;
+                let records = g.durable.pending(&identity, last_id);
+                if records.is_empty() {
+                    g.durable.set_live(&identity, &addr);
+                    break;
+                }
+                records
+            };
+            for (id, r) in records {
+                if let Err(e) = tx.push(r).await {
+                    warn!("notice to {}: {}", addr, e.to_string());
+                    return;
+                }
+                last_id = id;
+                count += 1;
+            }
+        }
+        info!("{} resumes {}, {} notices replayed.", addr, &identity, count);
+    }
+    pub(crate) async fn ack(&self, addr: &ConnectionId, id: usize) {
+        let dumps = {
+            let mut g = self.0.lock().await;
+            match g.durable.ack(addr, id) {
+                Some(identity) => g.dumps(vec![identity]),
+                None => return,
+            }
+        };
+        self.persist(dumps).await;
+    }
+    // some if the reliable report is seen already, the terminal didn't get the ack.
+    async fn report_seen(&self, nonce: u64, id: usize) -> Option<Option<ServantResult<()>>> {
+        let mut g = self.0.lock().await;
+        if !g.reports.contains_key(&nonce) && g.reports.len() >= REPORT_WINDOWS {
+            let idle = g
+                .reports
+                .iter()
+                .min_by_key(|(_, w)| w.used)
+                .map(|(nonce, _)| *nonce);
+            if let Some(idle) = idle {
+                g.reports.remove(&idle);
+            }
+        }
+        g.reports
+            .entry(nonce)
+            .or_insert_with(_ReportWindow::new)
+            .seen(id)
+    }
+    async fn report_served(&self, nonce: u64, id: usize, ret: ServantResult<()>) {
+        let mut g = self.0.lock().await;
+        if let Some(w) = g.reports.get_mut(&nonce) {
+            if let Some(r) = w.results.get_mut(&id) {
+                r.replace(ret);
+            }
+        }
+    }
+    async fn persist(&self, dumps: Vec<(Oid, Vec<u8>)>) {
+        if dumps.is_empty() {
+            return;
+        }
+        let store = match self.0.lock().await.store.clone() {
+            Some(s) => s,
+            None => return,
+        };
+        for (oid, bytes) in dumps {
+            if let Err(e) = store.store_bytes(&oid, &bytes).await {
+                warn!("durable notices of {}: {}", oid.name(), e.to_string());
+            }
         }
     }
 }
 
+// the connections a notice is sent to.
+#[derive(Clone, Copy)]
+enum Target<'a> {
+    All,
+    List(&'a [ConnectionId]),
+    Group(&'a str),
+    Topic(&'a str),
+}
+
 // --
 
+// the count of items a stream may still send to the terminal.
+struct _Credit {
+    m: Mutex<usize>,
+    cv: Condvar,
+}
+#[derive(Clone)]
+struct Credit(Arc<_Credit>);
+impl Credit {
+    fn new(credit: usize) -> Self {
+        Self(Arc::new(_Credit {
+            m: Mutex::new(credit),
+            cv: Condvar::new(),
+        }))
+    }
+    async fn grant(&self, credit: usize) {
+        let mut g = self.0.m.lock().await;
+        *g += credit;
+        self.0.cv.notify_one();
+    }
+    // false if the request is cancelled while it waits, or the terminal is gone.
+    async fn acquire(&self, cancel: &CancelHandle) -> bool {
+        let acquired = async {
+            let mut g = self.0.m.lock().await;
+            while *g == 0 {
+                g = self.0.cv.wait(g).await;
+            }
+            *g -= 1;
+        }
+        .fuse();
+        let cancelled = cancel.cancelled().fuse();
+        pin_mut!(acquired, cancelled);
+        select! {
+            _ = acquired => true,
+            _ = cancelled => false,
+        }
+    }
+}
+
+#[derive(Clone)]
+struct InFlight {
+    cancel: CancelHandle,
+    credit: Option<Credit>,
+    // the chunks of an upload go to its servant, till the upload ends.
+    chunks: Option<UnboundedSender<Vec<u8>>>,
+    // the count of chunks the terminal may still send, within the credit granted.
+    chunk_credit: Option<Arc<AtomicUsize>>,
+}
+// requests being served, by record id.
+type InFlightMap = Arc<Mutex<HashMap<usize, InFlight>>>;
+
+// what serving a request needs to know about its connection.
+#[derive(Clone, Copy)]
+struct ServeOptions {
+    upload_credit: usize,
+    max_response_size: usize,
+    format: Format,
+}
+
 pub(crate) struct Adapter {
     sr: ServantRegister,
     ar: AdapterRegister,
     // max_serve_count: usize,
     serve_count: Arc<Mutex<usize>>,
+    requests: InFlightMap,
 }
 
 impl Adapter {
@@ -92,57 +713,254 @@ impl Adapter {
             ar,
             // max_serve_count,
             serve_count: Arc::new(Mutex::new(max_serve_count)),
+            requests: Arc::new(Mutex::new(HashMap::new())),
         }
     }
-    pub(crate) async fn run(self, stream: TcpStream) -> std::io::Result<()> {
+    // the caller has reserved a slot for the connection in the register.
+    pub(crate) async fn run(self, conn: Connection) -> std::io::Result<()> {
         #[derive(Debug)]
         enum SelectedValue {
             ReadNone,
             WriteNone,
+            Tick,
             Read(Record),
             Write(Record),
         };
 
-        let addr = stream.peer_addr()?;
+        let limits = self.ar.limits().await;
+        conn.set_max_frame_size(limits.max_frame_size);
+        let Connection {
+            id: addr,
+            reader: mut read_framed,
+            writer: mut write_framed,
+            settings,
+        } = conn;
         info!("connected from {}", &addr);
-        let (reader, writer) = (&stream, &stream);
-        let read_framed = FramedRead::new(reader, RecordCodec::<u32, Record>::default());
-        let mut write_framed = FramedWrite::new(writer, RecordCodec::<u32, Record>::default());
-
-        // let sem = Semaphore::new(self.max_serve_count);
-        let (tx, rx) = unbounded();
-        self.ar.insert(addr, tx.clone()).await;
-
-        let _adapter_clean = DropGuard::new((addr, self.ar.clone()), |(a, ar)| {
+        // the slot reserved when it's accepted, it's taken by the connection inserted below.
+        let slot = DropGuard::new(self.ar.clone(), |ar| {
             task::block_on(async move {
-                info!("adapter from {} quit.", &addr);
-                ar.remove(&a).await;
+                ar.release().await;
             });
         });
+        if !self
+            .handshake(&addr, &settings, &mut read_framed, &mut write_framed)
+            .await?
+        {
+            self.ar.remove(&addr).await;
+            return Ok(());
+        }
+        let format = settings.format();
+
+        // let sem = Semaphore::new(self.max_serve_count);
+        let tx = self.ar.new_queue().await;
+        let rx = tx.receiver();
+        self.ar
+            .insert(addr.clone(), tx.clone(), settings.clone())
+            .await;
+        drop(slot);
+
+        // the requests of a terminal which is gone are cancelled, and the uploads see
+        // the end of their chunks.
+        let _adapter_clean = DropGuard::new(
+            (addr.clone(), self.ar.clone(), self.requests.clone()),
+            |(a, ar, r)| {
+                task::block_on(async move {
+                    info!("adapter from {} quit.", &a);
+                    ar.remove(&a).await;
+                    r.lock().await.drain().for_each(|(_, r)| r.cancel.cancel());
+                });
+            },
+        );
+
+        // the tick checks the idle timeout, and sends a ping when the terminal is quiet.
+        let (heartbeat_ms, idle_ms) = self.ar.heartbeat().await;
+        let tick_ms = match (heartbeat_ms, idle_ms) {
+            (0, idle) => idle,
+            (heartbeat, 0) => heartbeat,
+            (heartbeat, idle) => heartbeat.min(idle),
+        };
+        let mut interval: Pin<Box<dyn Stream<Item = ()> + Send>> = if tick_ms > 0 {
+            Box::pin(stream::interval(Duration::from_millis(tick_ms)))
+        } else {
+            Box::pin(futures::stream::pending())
+        };
+        let mut last_received = Instant::now();
+        let mut ping_id = 0;
+        let options = ServeOptions {
+            upload_credit: self.ar.upload_credit().await,
+            max_response_size: limits.max_response_size,
+            format,
+        };
 
         pin_mut!(read_framed, rx);
         loop {
             let value = select! {
                 from_terminal = read_framed.next().fuse() => match from_terminal {
-                    Some(record) => SelectedValue::Read(record?),
+                    Some(record) => SelectedValue::Read(record.map_err(|e| {
+                        warn!("read from {} failed: {}", &addr, e.to_string());
+                        e
+                    })?),
                     None => SelectedValue::ReadNone,
                 },
                 to_terminal = rx.next().fuse() => match to_terminal {
                     Some(record) => SelectedValue::Write(record),
                     None => SelectedValue::WriteNone,
                 },
+                _tick = interval.next().fuse() => SelectedValue::Tick,
             };
+            if let SelectedValue::Read(_) = &value {
+                last_received = Instant::now();
+            }
 
             match value {
+                SelectedValue::Read(Record::Hello { .. }) => {
+                    warn!("hello from {} after handshake is ignored.", &addr);
+                }
+                SelectedValue::Read(Record::Ping { id }) => {
+                    write_framed.send(Record::Pong { id }).await?;
+                }
+                SelectedValue::Read(Record::Pong { .. }) => {}
+                SelectedValue::Read(Record::Cancel { id }) => {
+                    if let Some(r) = self.requests.lock().await.get(&id) {
+                        info!("request {} from {} is cancelled.", id, &addr);
+                        r.cancel.cancel();
+                    }
+                }
+                SelectedValue::Read(Record::UploadChunk { id, chunk }) => {
+                    let r = self.requests.lock().await.get(&id).cloned();
+                    match r.map(|r| (r.chunks, r.chunk_credit)) {
+                        Some((Some(chunks), Some(credit))) => {
+                            // only the chunks are counted down here, so it never goes below 0
+                            // between the check and the count.
+                            if credit.load(Ordering::SeqCst) == 0 {
+                                warn!(
+                                    "upload {} from {} exceeds the credit granted, the connection is closed.",
+                                    id, &addr
+                                );
+                                break;
+                            }
+                            credit.fetch_sub(1, Ordering::SeqCst);
+                            chunks
+                                .unbounded_send(chunk)
+                                .unwrap_or_else(|e| warn!("{}", e.to_string()));
+                        }
+                        _ => warn!("upload {} from {} dosen't exist.", id, &addr),
+                    }
+                }
+                SelectedValue::Read(Record::UploadEnd { id }) => {
+                    if let Some(r) = self.requests.lock().await.get_mut(&id) {
+                        r.chunks.take();
+                    }
+                }
+                SelectedValue::Read(Record::Subscribe { topic }) => {
+                    info!("{} subscribes {}.", &addr, &topic);
+                    self.ar.subscribe(topic, &addr).await;
+                }
+                SelectedValue::Read(Record::Unsubscribe { topic }) => {
+                    info!("{} unsubscribes {}.", &addr, &topic);
+                    self.ar.unsubscribe(&topic, &addr).await;
+                }
+                SelectedValue::Read(Record::Resume { identity, last_id }) => {
+                    self.ar.resume(identity, &addr, last_id).await;
+                }
+                SelectedValue::Read(Record::NoticeAck { id }) => {
+                    self.ar.ack(&addr, id).await;
+                }
+                SelectedValue::Read(Record::StreamCredit { id, credit }) => {
+                    let r = self.requests.lock().await.get(&id).cloned();
+                    if let Some(c) = r.and_then(|r| r.credit) {
+                        c.grant(credit).await;
+                    }
+                }
+                SelectedValue::Tick => {
+                    let idle = last_received.elapsed();
+                    if idle_ms > 0 && idle >= Duration::from_millis(idle_ms) {
+                        warn!(
+                            "no record from {} in {} milliseconds, the connection is dead.",
+                            &addr,
+                            idle.as_millis()
+                        );
+                        break;
+                    }
+                    if heartbeat_ms > 0 && idle >= Duration::from_millis(heartbeat_ms) {
+                        ping_id += 1;
+                        write_framed.send(Record::Ping { id: ping_id }).await?;
+                    }
+                }
+                SelectedValue::Read(record) if !is_served(&record) => {
+                    warn!("record from {} is only sent to terminals, it's dropped.", &addr);
+                }
                 SelectedValue::Read(record) => {
                     let tx2 = tx.clone();
+                    if let Err(e) = check_record(&record, &limits) {
+                        // an oversized report is only logged, it's too large to print.
+                        warn!("record from {} is refused: {}", &addr, e);
+                        match record {
+                            Record::Report { .. } => {}
+                            Record::ReliableReport { id, .. } => {
+                                report_ack(&tx2, format, id, Err(e.into()), false)
+                            }
+                            record => out_of_service(tx2, format, record, &e).await,
+                        }
+                        continue;
+                    }
+                    if self.ar.is_closing().await {
+                        out_of_service(tx2, format, record, "server is shutting down").await;
+                        continue;
+                    }
                     let mut g = self.serve_count.lock().await;
                     if *g == 0 {
-                        out_of_service(tx2, record).await;
+                        out_of_service(tx2, format, record, "serve count is 0").await;
                     } else {
+                        if let Record::ReliableReport { nonce, id, .. } = &record {
+                            if let Some(ret) = self.ar.report_seen(*nonce, *id).await {
+                                info!("report {} from {} is seen already.", id, &addr);
+                                // still being served, it's acknowledged when it's done.
+                                if let Some(ret) = ret {
+                                    report_ack(&tx2, format, *id, ret, false);
+                                }
+                                continue;
+                            }
+                        }
                         *g -= 1;
+                        let record = match record {
+                            Record::Request { id, ctx, oid, req } => {
+                                let ctx = self.track(&addr, id, ctx, None, format).await;
+                                Record::Request { id, ctx, oid, req }
+                            }
+                            Record::StreamRequest {
+                                id,
+                                ctx,
+                                oid,
+                                req,
+                                credit,
+                            } => {
+                                let ctx = self.track(&addr, id, ctx, Some(credit), format).await;
+                                Record::StreamRequest {
+                                    id,
+                                    ctx,
+                                    oid,
+                                    req,
+                                    credit,
+                                }
+                            }
+                            Record::UploadStart { id, ctx, oid, req } => {
+                                let ctx = self.track(&addr, id, ctx, None, format).await;
+                                Record::UploadStart { id, ctx, oid, req }
+                            }
+                            record => record,
+                        };
                         let sr = self.sr.clone();
-                        task::spawn(serve2(self.serve_count.clone(), sr, tx2, record));
+                        self.ar.begin_serve().await;
+                        task::spawn(serve2(
+                            self.serve_count.clone(),
+                            self.ar.clone(),
+                            sr,
+                            self.requests.clone(),
+                            options,
+                            tx2,
+                            record,
+                        ));
                     }
                     //     let g = sem.lock().await;
                     //     let tx2 = tx.clone();
@@ -164,32 +982,228 @@ impl Adapter {
 
         Ok(())
     }
+    // the request can be cancelled and, if it's a stream, granted credit by the terminal.
+    // its context carries the connection, the cancel handle, the deadline and the format
+    // to the servant.
+    async fn track(
+        &self,
+        addr: &ConnectionId,
+        id: usize,
+        ctx: Option<Context>,
+        credit: Option<usize>,
+        format: Format,
+    ) -> Option<Context> {
+        let cancel = CancelHandle::new();
+        self.requests.lock().await.insert(
+            id,
+            InFlight {
+                cancel: cancel.clone(),
+                credit: credit.map(Credit::new),
+                chunks: None,
+                chunk_credit: None,
+            },
+        );
+        let now = Instant::now();
+        ctx.map(|mut c| {
+            c.connection_id.replace(addr.clone());
+            c.set_cancel_handle(cancel);
+            c.set_format(format);
+            if let Some(t) = c.timeout_millisecond {
+                c.set_deadline(now + Duration::from_millis(t));
+            }
+            c
+        })
+    }
+    // the first record must be a hello. a terminal without handshake sends a request first,
+    // it gets the rejection in a response which it can decode. the format negotiated
+    // takes effect after the welcome.
+    async fn handshake(
+        &self,
+        addr: &ConnectionId,
+        settings: &FrameSettings,
+        reader: &mut RecordReader,
+        writer: &mut RecordWriter,
+    ) -> std::io::Result<bool> {
+        let timeout = Duration::from_millis(self.ar.handshake_timeout_ms().await);
+        let first = match future::timeout(timeout, reader.next()).await {
+            Ok(Some(record)) => record?,
+            Ok(None) => return Ok(false),
+            Err(_) => {
+                warn!("handshake with {} timed out.", addr);
+                return Ok(false);
+            }
+        };
+        let reply = match first {
+            Record::Hello {
+                protocol,
+                version,
+                serializers,
+                compressions,
+                auth,
+            } => match handshake::negotiate(protocol, &serializers, &compressions) {
+                Ok(welcome) => {
+                    info!("hello from {}, protocol: {}, version: {}", addr, protocol, version);
+                    if self.ar.authenticate(addr, auth.as_deref()).await {
+                        welcome
+                    } else {
+                        Record::Reject {
+                            reason: "authentication failed.".to_string(),
+                        }
+                    }
+                }
+                Err(reason) => Record::Reject { reason },
+            },
+            Record::Request { id, oid, .. } => {
+                let ret: ServantResult<Vec<u8>> = Err(format!(
+                    "handshake is required, server protocol version is {}.",
+                    handshake::PROTOCOL_VERSION
+                )
+                .into());
+                match bincode::serialize(&ret) {
+                    Ok(ret) => Record::Response { id, oid, ret },
+                    Err(e) => {
+                        warn!("{}", e.to_string());
+                        return Ok(false);
+                    }
+                }
+            }
+            _ => Record::Reject {
+                reason: "handshake is required.".to_string(),
+            },
+        };
+        let welcome = matches!(reply, Record::Welcome { .. });
+        if !welcome {
+            warn!("handshake with {} is rejected: {:?}", addr, &reply);
+        }
+        let negotiated = match &reply {
+            Record::Welcome {
+                serializer,
+                compression,
+                ..
+            } => Format::from_name(serializer).zip(Compression::from_name(compression)),
+            _ => None,
+        };
+        writer.send(reply).await?;
+        if let Some((format, compression)) = negotiated {
+            settings.set_format(format);
+            let threshold = self.ar.compression_threshold().await;
+            settings.set_compression(compression, threshold);
+        }
+        Ok(welcome)
+    }
+}
+
+// the records served by servants, the others which aren't handled in `Adapter::run`
+// are sent to terminals only.
+fn is_served(record: &Record) -> bool {
+    matches!(
+        record,
+        Record::Report { .. }
+            | Record::ReliableReport { .. }
+            | Record::Request { .. }
+            | Record::StreamRequest { .. }
+            | Record::UploadStart { .. }
+    )
+}
+
+fn check_size(kind: &str, size: usize, max: usize) -> Result<(), String> {
+    if size > max {
+        Err(format!("{} of {} bytes exceeds the max {} size {}.", kind, size, kind, max))
+    } else {
+        Ok(())
+    }
 }
 
-async fn out_of_service(mut tx: UnboundedSender<Record>, record: Record) {
+fn check_record(record: &Record, limits: &config::LimitData) -> Result<(), String> {
+    match record {
+        Record::Report { msg, .. } | Record::ReliableReport { msg, .. } => {
+            check_size("report", msg.len(), limits.max_report_size)
+        }
+        Record::Request { req, .. }
+        | Record::StreamRequest { req, .. }
+        | Record::UploadStart { req, .. } => {
+            check_size("request", req.len(), limits.max_request_size)
+        }
+        _ => Ok(()),
+    }
+}
+
+// an oversized response is replaced by an error, which the terminal can receive.
+fn check_response(ret: ServantResult<Vec<u8>>, max: usize) -> ServantResult<Vec<u8>> {
+    match ret {
+        Ok(r) => check_size("response", r.len(), max)
+            .map(|_| r)
+            .map_err(ServantError::from),
+        Err(e) => Err(e),
+    }
+}
+
+fn report_ack(tx: &RecordQueue, format: Format, id: usize, ret: ServantResult<()>, retry: bool) {
+    match format.serialize(&ret) {
+        Ok(ret) => tx
+            .force(Record::ReportAck { id, ret, retry })
+            .unwrap_or_else(|e| warn!("{}", e.to_string())),
+        Err(e) => warn!("{}", e.to_string()),
+    }
+}
+
+async fn out_of_service(tx: RecordQueue, format: Format, record: Record, reason: &str) {
     match record {
         Record::Report { id, oid, msg } => {
             warn!(
-                "serve count is 0. Report: {:?}",
+                "{}. Report: {:?}",
+                reason,
                 Record::Report { id, oid, msg }
             );
         }
+        // the server is busy or shutting down, the terminal sends it again later.
+        Record::ReliableReport { id, oid, .. } => {
+            warn!("{}. Report {} to {}.", reason, id, &oid);
+            report_ack(&tx, format, id, Err(reason.into()), true);
+        }
         Record::Request { id, ctx, oid, req } => {
             let _ctx = ctx;
             let _req = req;
-            let ret: ServantResult<Vec<u8>> = Err(format!("serve count is 0").into());
-            match bincode::serialize(&ret) {
+            let ret: ServantResult<Vec<u8>> = Err(reason.into());
+            match format.serialize(&ret) {
                 Ok(ret) => {
                     let record = Record::Response { id, oid, ret };
-                    if let Err(e) = tx.send(record).await {
+                    if let Err(e) = tx.push(record).await {
                         warn!("{}", e.to_string());
                     }
                 }
                 Err(e) => warn!("{}", e.to_string()),
             }
         }
-        Record::Response { .. } => unreachable!(),
-        Record::Notice { .. } => unreachable!(),
+        Record::UploadStart { id, oid, .. } => {
+            let ret: ServantResult<Vec<u8>> = Err(reason.into());
+            match format.serialize(&ret) {
+                Ok(ret) => {
+                    let record = Record::Response {
+                        id,
+                        oid: Some(oid),
+                        ret,
+                    };
+                    if let Err(e) = tx.push(record).await {
+                        warn!("{}", e.to_string());
+                    }
+                }
+                Err(e) => warn!("{}", e.to_string()),
+            }
+        }
+        Record::StreamRequest { id, .. } => {
+            let ret: ServantResult<()> = Err(reason.into());
+            match format.serialize(&ret) {
+                Ok(ret) => {
+                    if let Err(e) = tx.push(Record::StreamEnd { id, ret }).await {
+                        warn!("{}", e.to_string());
+                    }
+                }
+                Err(e) => warn!("{}", e.to_string()),
+            }
+        }
+        // the others are refused by the adapter before they get here.
+        _ => warn!("{}. The record isn't served.", reason),
     };
 }
 /*
@@ -240,14 +1254,23 @@ async fn serve(
 */
 async fn serve2(
     count: Arc<Mutex<usize>>,
+    ar: AdapterRegister,
     sr: ServantRegister,
-    mut tx: UnboundedSender<Record>,
+    requests: InFlightMap,
+    options: ServeOptions,
+    tx: RecordQueue,
     record: Record,
 ) {
-    let _guard = DropGuard::new(count, |c| {
+    let ServeOptions {
+        upload_credit,
+        max_response_size,
+        format,
+    } = options;
+    let _guard = DropGuard::new((count, ar.clone()), |(c, ar)| {
         task::block_on(async move {
             let mut g = c.lock().await;
             *g += 1;
+            ar.end_serve().await;
         });
     });
     match record {
@@ -255,36 +1278,252 @@ async fn serve2(
             let _id = id;
             if let Some(servant) = sr.find_report_servant(&oid).await {
                 servant.lock().await.serve(msg);
+            } else if let Some(servant) = sr.find_async_report_servant(&oid).await {
+                servant.lock().await.serve(msg).await;
             } else {
                 warn!("{} dosen't exist.", &oid);
             }
         }
+        Record::ReliableReport { nonce, id, oid, msg } => {
+            let ret: ServantResult<()> = if let Some(servant) = sr.find_report_servant(&oid).await {
+                servant.lock().await.serve(msg);
+                Ok(())
+            } else if let Some(servant) = sr.find_async_report_servant(&oid).await {
+                servant.lock().await.serve(msg).await;
+                Ok(())
+            } else {
+                Err(format!("{} dosen't exist.", &oid).into())
+            };
+            ar.report_served(nonce, id, ret.clone()).await;
+            report_ack(&tx, format, id, ret, false);
+        }
         Record::Request { id, ctx, oid, req } => {
-            let ret: ServantResult<Vec<u8>> = if let Some(oid) = &oid {
-                if let Some(servant) = sr.find_servant(oid).await {
-                    Ok(servant.lock().await.serve(ctx, req))
+            // gone with the connection.
+            let cancel = match requests.lock().await.get(&id) {
+                Some(r) => r.cancel.clone(),
+                None => return,
+            };
+            let _request_clean = DropGuard::new((requests, id), |(r, id)| {
+                task::block_on(async move {
+                    r.lock().await.remove(&id);
+                });
+            });
+            let deadline = ctx.as_ref().and_then(|c| c.deadline());
+            let running = AtomicBool::new(false);
+            let serve = async {
+                let ret: ServantResult<Vec<u8>> = if let Some(oid) = &oid {
+                    if let Some(servant) = sr.find_servant(oid).await {
+                        let mut q = servant.lock().await;
+                        running.store(true, Ordering::SeqCst);
+                        Ok(q.serve(ctx, req))
+                    } else if let Some(servant) = sr.find_async_servant(oid).await {
+                        let mut q = servant.lock().await;
+                        running.store(true, Ordering::SeqCst);
+                        Ok(q.serve(ctx, req).await)
+                    } else {
+                        Err(format!("{} dosen't exist.", &oid).into())
+                    }
                 } else {
-                    Err(format!("{} dosen't exist.", &oid).into())
+                    if let Some(watch) = sr.watch_servant().await {
+                        let mut q = watch.lock().await;
+                        running.store(true, Ordering::SeqCst);
+                        Ok(q.serve(req))
+                    } else if let Some(watch) = sr.async_watch_servant().await {
+                        let mut q = watch.lock().await;
+                        running.store(true, Ordering::SeqCst);
+                        Ok(q.serve(req).await)
+                    } else {
+                        Err("help servant dosen't exist.".into())
+                    }
+                };
+                ret
+            };
+            let ret = match serve_in_time(id, &cancel, deadline, &running, serve).await {
+                Some(ret) => check_response(ret, max_response_size),
+                None => return,
+            };
+            match format.serialize(&ret) {
+                Ok(ret) => {
+                    let record = Record::Response { id, oid, ret };
+                    if let Err(e) = tx.push(record).await {
+                        warn!("{}", e.to_string());
+                    }
                 }
-            } else {
-                if let Some(watch) = sr.watch_servant().await {
-                    let mut q = watch.lock().await;
-                    Ok(q.serve(req))
-                } else {
-                    Err("help servant dosen't exist.".into())
+                Err(e) => warn!("{}", e.to_string()),
+            }
+        }
+        Record::StreamRequest { id, ctx, oid, req, .. } => {
+            // gone with the connection.
+            let (cancel, credit) = match requests.lock().await.get(&id) {
+                Some(r) => (r.cancel.clone(), r.credit.clone()),
+                None => return,
+            };
+            let credit = credit.unwrap_or_else(|| Credit::new(0));
+            let _request_clean = DropGuard::new((requests, id), |(r, id)| {
+                task::block_on(async move {
+                    r.lock().await.remove(&id);
+                });
+            });
+            let deadline = ctx.as_ref().and_then(|c| c.deadline());
+            let running = AtomicBool::new(false);
+            let tx2 = tx.clone();
+            let cancel2 = cancel.clone();
+            let running2 = &running;
+            let serve = async move {
+                let tx = tx2;
+                let servant = match sr.find_stream_servant(&oid).await {
+                    Some(servant) => servant,
+                    None => return Err(format!("{} dosen't exist.", &oid).into()),
+                };
+                // the stream returned may be dropped between its items.
+                let mut items = {
+                    let mut q = servant.lock().await;
+                    running2.store(true, Ordering::SeqCst);
+                    let items = q.serve(ctx, req).await;
+                    running2.store(false, Ordering::SeqCst);
+                    items
+                };
+                while let Some(item) = items.next().await {
+                    check_size("response", item.len(), max_response_size)?;
+                    if !credit.acquire(&cancel2).await {
+                        return Err("cancelled.".into());
+                    }
+                    if let Err(e) = tx.push(Record::StreamItem { id, item }).await {
+                        return Err(e.to_string().into());
+                    }
                 }
+                let ret: ServantResult<()> = Ok(());
+                ret
             };
-            match bincode::serialize(&ret) {
+            let ret = match serve_in_time(id, &cancel, deadline, &running, serve).await {
+                Some(ret) => ret,
+                None => return,
+            };
+            match format.serialize(&ret) {
                 Ok(ret) => {
-                    let record = Record::Response { id, oid, ret };
-                    if let Err(e) = tx.send(record).await {
+                    if let Err(e) = tx.push(Record::StreamEnd { id, ret }).await {
                         warn!("{}", e.to_string());
                     }
                 }
                 Err(e) => warn!("{}", e.to_string()),
             }
         }
-        Record::Response { .. } => unreachable!(),
-        Record::Notice { .. } => unreachable!(),
+        Record::UploadStart { id, ctx, oid, req } => {
+            let (chunks_tx, chunks_rx) = unbounded();
+            let granted = Arc::new(AtomicUsize::new(upload_credit));
+            // the sender is only kept in the map, it's dropped with the connection.
+            let cancel = match requests.lock().await.get_mut(&id) {
+                Some(r) => {
+                    r.chunks.replace(chunks_tx);
+                    r.chunk_credit.replace(granted.clone());
+                    r.cancel.clone()
+                }
+                None => return,
+            };
+            let _request_clean = DropGuard::new((requests, id), |(r, id)| {
+                task::block_on(async move {
+                    r.lock().await.remove(&id);
+                });
+            });
+            // the terminal sends chunks only within the credit, which is granted
+            // again as the servant consumes them.
+            let credit_tx = tx.clone();
+            let mut consumed = 0;
+            let chunks = Box::pin(chunks_rx.map(move |chunk| {
+                consumed += 1;
+                if consumed * 2 >= upload_credit {
+                    let credit = std::mem::take(&mut consumed);
+                    granted.fetch_add(credit, Ordering::SeqCst);
+                    credit_tx
+                        .force(Record::StreamCredit { id, credit })
+                        .unwrap_or_else(|e| warn!("{}", e.to_string()));
+                }
+                chunk
+            }));
+            let record = Record::StreamCredit {
+                id,
+                credit: upload_credit,
+            };
+            if let Err(e) = tx.push(record).await {
+                warn!("{}", e.to_string());
+                return;
+            }
+            let deadline = ctx.as_ref().and_then(|c| c.deadline());
+            let running = AtomicBool::new(false);
+            let serve = async {
+                let ret: ServantResult<Vec<u8>> = match sr.find_upload_servant(&oid).await {
+                    Some(servant) => {
+                        let mut q = servant.lock().await;
+                        running.store(true, Ordering::SeqCst);
+                        Ok(q.serve(ctx, req, chunks).await)
+                    }
+                    None => Err(format!("{} dosen't exist.", &oid).into()),
+                };
+                ret
+            };
+            let ret = match serve_in_time(id, &cancel, deadline, &running, serve).await {
+                Some(ret) => check_response(ret, max_response_size),
+                None => return,
+            };
+            match format.serialize(&ret) {
+                Ok(ret) => {
+                    let record = Record::Response {
+                        id,
+                        oid: Some(oid),
+                        ret,
+                    };
+                    if let Err(e) = tx.push(record).await {
+                        warn!("{}", e.to_string());
+                    }
+                }
+                Err(e) => warn!("{}", e.to_string()),
+            }
+        }
+        // the others are refused by the adapter before they get here.
+        _ => warn!("the record isn't served."),
     };
 }
+
+// the deadline counts from when the adapter reads the request, the time on the way
+// and before it isn't known.
+// a cancelled or expired request is dropped while it waits for its servant. once the
+// servant runs, it's only told by the cancel handle in its context, and finishes or bails
+// out by itself. `None` if it's cancelled, the terminal expects nothing then.
+async fn serve_in_time<T, F>(
+    id: usize,
+    cancel: &CancelHandle,
+    deadline: Option<Instant>,
+    running: &AtomicBool,
+    serve: F,
+) -> Option<ServantResult<T>>
+where
+    F: Future<Output = ServantResult<T>>,
+{
+    let serve = serve.fuse();
+    let cancelled = cancel.cancelled().fuse();
+    let expired = async {
+        match deadline {
+            Some(d) => task::sleep(d.saturating_duration_since(Instant::now())).await,
+            None => future::pending::<()>().await,
+        }
+    }
+    .fuse();
+    pin_mut!(serve, cancelled, expired);
+    let expired = select! {
+        ret = serve => return Some(ret),
+        _ = cancelled => false,
+        _ = expired => true,
+    };
+    if running.load(Ordering::SeqCst) {
+        info!("request {} is running, the servant is told to stop.", id);
+        cancel.cancel();
+        let _ = serve.await;
+    }
+    if expired {
+        info!("request {} is expired.", id);
+        Some(Err(ServantError::Timeout))
+    } else {
+        info!("request {} is dropped due to cancel.", id);
+        None
+    }
+}
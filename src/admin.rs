@@ -2,13 +2,12 @@
 use crate::{
     self as servant,
     adapter::AdapterRegister,
-    servant::{Context, ServantRegister, UserCookie, Oid},
+    servant::{ConnectionId, Context, ServantRegister, UserCookie, Oid},
     task,
     utilities::{RemoteError, RemoteResult},
 };
 use log::info;
 use rand::random;
-use std::{net::SocketAddr};
 
 // --
 
@@ -16,7 +15,7 @@ use std::{net::SocketAddr};
 pub trait Admin {
     fn acquire(&self, password: String) -> RemoteResult<UserCookie>;
     fn shutdown(&self, passcode: usize) -> RemoteResult<()>;
-    fn adapter_list(&self) -> RemoteResult<Vec<SocketAddr>>;
+    fn adapter_list(&self) -> RemoteResult<Vec<ConnectionId>>;
     fn servants(&self) -> RemoteResult<Vec<Oid>>;
     fn report_servants(&self) -> RemoteResult<Vec<Oid>>;
     fn watch_servant(&self) -> RemoteResult<bool>;
@@ -72,8 +71,8 @@ impl Admin for AdminEntity {
     fn shutdown(&self, ctx: Option<Context>, passcode: usize) -> RemoteResult<()> {
         if self.check_user_cookie(ctx) && passcode != 0 && passcode == self.shutdown_code {
             let ar = self.ar.clone();
-            task::block_on(async {
-                ar.clean().await;
+            task::spawn(async move {
+                ar.shutdown().await;
             });
             info!("shutdown by admin");
             Ok(())
@@ -82,7 +81,7 @@ impl Admin for AdminEntity {
             Err(on_the_remote!("invalid context".to_owned()))
         }
     }
-    fn adapter_list(&self, ctx: Option<Context>) -> RemoteResult<Vec<SocketAddr>> {
+    fn adapter_list(&self, ctx: Option<Context>) -> RemoteResult<Vec<ConnectionId>> {
         if !self.check_user_cookie(ctx) {
             return Err(on_the_remote!("invalid context".to_owned()));
         }
@@ -109,7 +108,7 @@ impl Admin for AdminEntity {
             return Err(on_the_remote!("invalid context".to_owned()));
         }
         task::block_on(async { 
-            Ok(self.sr.watch_servant().await.is_some())
+            Ok(self.sr.has_watch_servant().await)
         })
     }
     fn evictor_list(&self, ctx: Option<Context>) -> RemoteResult<Vec<Oid>> {
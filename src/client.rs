@@ -1,27 +1,77 @@
 // -- client.rs --
 
 use {
-    crate::{config, servant::ServantResult, terminal::Terminal},
+    crate::{
+        config,
+        servant::ServantResult,
+        sync::Arc,
+        terminal::Terminal,
+        transport::{self, Connector},
+    },
 };
 
 // --
 
 pub struct Client {
     config: config::Client,
+    auth: Option<Vec<u8>>,
 }
 impl Client {
     pub fn new() -> Self {
         Self {
             config: config::Client::load(),
+            auth: None,
         }
     }
+    pub fn set_auth(&mut self, auth: Vec<u8>) {
+        self.auth.replace(auth);
+    }
     pub async fn connect_to(&self, addr: String) -> ServantResult<Terminal> {
+        let connector =
+            transport::connector(&addr, self.config.tls.as_ref()).map_err(|e| e.to_string())?;
+        self.connect(addr, connector).await
+    }
+    pub async fn connect_with<C: Connector + 'static>(
+        &self,
+        addr: String,
+        connector: C,
+    ) -> ServantResult<Terminal> {
+        self.connect(addr, Arc::new(connector)).await
+    }
+    async fn connect(&self, addr: String, connector: Arc<dyn Connector>) -> ServantResult<Terminal> {
         let t = Terminal::new(
             addr,
             self.config.invoke_timeout_in_terminal,
             self.config.token_count_by_terminal,
             self.config.callback_count_by_terminal,
         );
+        t.set_connector(connector).await;
+        t.set_heartbeat(
+            self.config.heartbeat_interval_millisecond,
+            self.config.idle_timeout_millisecond,
+        )
+        .await;
+        t.set_stream_credit(self.config.stream_credit_by_terminal).await;
+        t.set_queue(
+            self.config.queue_capacity_by_terminal,
+            self.config.overflow_policy,
+        )
+        .await;
+        t.set_limits(self.config.limits.clone()).await;
+        t.set_serializers(self.config.serializers.clone()).await;
+        t.set_compressions(
+            self.config.compressions.clone(),
+            self.config.compression_threshold,
+        )
+        .await;
+        if let Some(auth) = self.auth.as_ref() {
+            t.set_auth(auth.clone()).await;
+        }
+        if let Some(identity) = self.config.identity.as_ref() {
+            t.set_identity(identity).await;
+        }
+        t.set_report_retry(self.config.report_retry.clone()).await;
+        t.set_reconnect(self.config.reconnect.clone()).await;
         if let Err(e) = t.connect_to().await {
             Err(e.to_string().into())
         } else {
@@ -0,0 +1,151 @@
+// -- compress.rs --
+
+use std::io;
+
+// --
+
+/// the compression of the payloads on a connection, negotiated in the handshake.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum Compression {
+    None,
+    Zstd,
+    Lz4,
+}
+impl Default for Compression {
+    fn default() -> Self {
+        Self::None
+    }
+}
+
+impl Compression {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::None => "none",
+            Self::Zstd => "zstd",
+            Self::Lz4 => "lz4",
+        }
+    }
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "none" => Some(Self::None),
+            "zstd" => Some(Self::Zstd),
+            "lz4" => Some(Self::Lz4),
+            _ => None,
+        }
+    }
+    /// the compressions built in, depending on the features `zstd` and `lz4`.
+    pub fn supported() -> Vec<&'static str> {
+        let mut v = vec![Self::None.name()];
+        if cfg!(feature = "zstd") {
+            v.push(Self::Zstd.name());
+        }
+        if cfg!(feature = "lz4") {
+            v.push(Self::Lz4.name());
+        }
+        v
+    }
+    pub(crate) fn compress(&self, data: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            Self::None => Ok(data.to_vec()),
+            #[cfg(feature = "zstd")]
+            Self::Zstd => zstd::stream::encode_all(data, 0),
+            #[cfg(feature = "lz4")]
+            Self::Lz4 => Ok(lz4_flex::compress_prepend_size(data)),
+            #[allow(unreachable_patterns)]
+            _ => Err(self.disabled()),
+        }
+    }
+    // `max` bounds the decompressed size, a small payload can't expand without limit.
+    pub(crate) fn decompress(&self, data: &[u8], max: usize) -> io::Result<Vec<u8>> {
+        let data = match self {
+            Self::None => data.to_vec(),
+            #[cfg(feature = "zstd")]
+            Self::Zstd => {
+                use std::io::Read;
+                let mut buf = Vec::new();
+                zstd::stream::Decoder::new(data)?
+                    .take(max as u64 + 1)
+                    .read_to_end(&mut buf)?;
+                buf
+            }
+            #[cfg(feature = "lz4")]
+            Self::Lz4 => {
+                if data.len() < 4 {
+                    return Err(invalid_data("lz4 payload is truncated."));
+                }
+                let mut size = [0u8; 4];
+                size.copy_from_slice(&data[..4]);
+                if u32::from_le_bytes(size) as usize > max {
+                    return Err(too_large(max));
+                }
+                lz4_flex::decompress_size_prepended(data)
+                    .map_err(|e| invalid_data(&format!("{:?}", e)))?
+            }
+            #[allow(unreachable_patterns)]
+            _ => return Err(self.disabled()),
+        };
+        if data.len() > max {
+            Err(too_large(max))
+        } else {
+            Ok(data)
+        }
+    }
+    fn disabled(&self) -> io::Error {
+        invalid_data(&format!("the {} feature is disabled.", self.name()))
+    }
+}
+
+fn invalid_data(e: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+}
+
+fn too_large(max: usize) -> io::Error {
+    invalid_data(&format!("decompressed payload exceeds the max frame size {}.", max))
+}
+
+/// the payload bytes before and after compression, on one connection.
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
+pub struct CompressionStats {
+    pub compression: String,
+    pub raw_sent: u64,
+    pub wire_sent: u64,
+    pub raw_received: u64,
+    pub wire_received: u64,
+}
+impl CompressionStats {
+    /// the bytes on the wire per payload byte sent, 1.0 if nothing is sent.
+    pub fn ratio_sent(&self) -> f64 {
+        if self.raw_sent == 0 {
+            1.0
+        } else {
+            self.wire_sent as f64 / self.raw_sent as f64
+        }
+    }
+    pub fn ratio_received(&self) -> f64 {
+        if self.raw_received == 0 {
+            1.0
+        } else {
+            self.wire_received as f64 / self.raw_received as f64
+        }
+    }
+}
+
+// --
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compress_test() {
+        let data = vec![7u8; 4096];
+        for name in Compression::supported() {
+            let c = Compression::from_name(name).unwrap();
+            assert_eq!(c.name(), name);
+            let buf = c.compress(&data).unwrap();
+            assert_eq!(c.decompress(&buf, data.len()).unwrap(), data);
+            assert!(c.decompress(&buf, data.len() - 1).is_err());
+        }
+        assert!(Compression::from_name("gzip").is_none());
+    }
+}
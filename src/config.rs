@@ -2,6 +2,7 @@
 
 cfg_server_or_client! {
 use {
+    crate::queue::OverflowPolicy,
     log::{info, warn},
     std::{
         fs::File,
@@ -40,6 +41,27 @@ fn store_json_str(file_name: &str, json_str: &str) {
         }
     };
 }
+
+/// the max sizes in bytes. a frame carries one record, the others are the payloads in it.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct LimitData {
+    pub max_frame_size: usize,
+    pub max_request_size: usize,
+    pub max_response_size: usize,
+    pub max_notice_size: usize,
+    pub max_report_size: usize,
+}
+impl Default for LimitData {
+    fn default() -> Self {
+        Self {
+            max_frame_size: 16 * 1024 * 1024,
+            max_request_size: 8 * 1024 * 1024,
+            max_response_size: 8 * 1024 * 1024,
+            max_notice_size: 1024 * 1024,
+            max_report_size: 1024 * 1024,
+        }
+    }
+}
 }
 
 // --
@@ -82,6 +104,42 @@ impl AdminData {
 }
 }
 
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub enum StorageData {
+    Memory,
+    Sqlite(String),
+    Directory(String),
+}
+impl Default for StorageData {
+    fn default() -> Self {
+        Self::Memory
+    }
+}
+
+/// the notices kept for each terminal identity, till they're acknowledged.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct DurableNoticeData {
+    pub max_count: usize,
+    pub max_age_second: u64,
+    pub persist: bool,
+}
+impl Default for DurableNoticeData {
+    fn default() -> Self {
+        Self {
+            max_count: 1024,
+            max_age_second: 3600,
+            persist: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct TlsServerData {
+    pub cert: String,
+    pub key: String,
+    pub client_ca: Option<String>,
+}
+
 // #[cfg_attr(test, derive(Debug))]
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
 pub struct Server {
@@ -90,11 +148,60 @@ pub struct Server {
     pub max_count_of_evictor_list: usize,
     pub max_count_of_connection: usize,
     pub serve_count_by_adapter: usize,
+    #[serde(default)]
+    pub storage: StorageData,
+    #[serde(default)]
+    pub checkpoint_interval_second: u64,
+    #[serde(default = "Server::default_shutdown_deadline")]
+    pub shutdown_deadline_millisecond: u64,
+    #[serde(default = "Server::default_handshake_timeout")]
+    pub handshake_timeout_millisecond: u64,
+    #[serde(default = "Server::default_heartbeat_interval")]
+    pub heartbeat_interval_millisecond: u64,
+    #[serde(default = "Server::default_idle_timeout")]
+    pub idle_timeout_millisecond: u64,
+    #[serde(default = "Server::default_upload_credit")]
+    pub upload_credit_by_adapter: usize,
+    #[serde(default = "Server::default_queue_capacity")]
+    pub queue_capacity_by_adapter: usize,
+    #[serde(default)]
+    pub overflow_policy: OverflowPolicy,
+    #[serde(default)]
+    pub limits: LimitData,
+    #[serde(default = "Server::default_compression_threshold")]
+    pub compression_threshold: usize,
+    #[serde(default)]
+    pub durable_notice: DurableNoticeData,
+    #[serde(default)]
+    pub tls: Option<TlsServerData>,
+    #[serde(default)]
+    pub unix_socket_mode: Option<u32>,
 }
 impl Server {
     fn file_name() -> &'static str {
         "./server.json"
     }
+    fn default_shutdown_deadline() -> u64 {
+        5000
+    }
+    fn default_handshake_timeout() -> u64 {
+        5000
+    }
+    fn default_heartbeat_interval() -> u64 {
+        10000
+    }
+    fn default_idle_timeout() -> u64 {
+        30000
+    }
+    fn default_upload_credit() -> usize {
+        16
+    }
+    fn default_queue_capacity() -> usize {
+        1024
+    }
+    fn default_compression_threshold() -> usize {
+        1024
+    }
     pub fn load() -> Self {
         let json_str = read_json_str(Self::file_name());
         match serde_json::from_str(&json_str) {
@@ -108,6 +215,20 @@ impl Server {
                     max_count_of_evictor_list: 5,
                     max_count_of_connection: 10,
                     serve_count_by_adapter: 3,
+                    storage: StorageData::default(),
+                    checkpoint_interval_second: 0,
+                    shutdown_deadline_millisecond: Self::default_shutdown_deadline(),
+                    handshake_timeout_millisecond: Self::default_handshake_timeout(),
+                    heartbeat_interval_millisecond: Self::default_heartbeat_interval(),
+                    idle_timeout_millisecond: Self::default_idle_timeout(),
+                    upload_credit_by_adapter: Self::default_upload_credit(),
+                    queue_capacity_by_adapter: Self::default_queue_capacity(),
+                    overflow_policy: OverflowPolicy::default(),
+                    limits: LimitData::default(),
+                    compression_threshold: Self::default_compression_threshold(),
+                    durable_notice: DurableNoticeData::default(),
+                    tls: None,
+                    unix_socket_mode: None,
                 }
             }
         }
@@ -133,6 +254,57 @@ impl Drop for Server {
 // --
 
 cfg_client! {
+/// how a reliable report is sent again till it's acknowledged.
+/// the delay doubles after each attempt, up to the max.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct ReportRetryData {
+    pub max_pending: usize,
+    pub initial_delay_millisecond: u64,
+    pub max_delay_millisecond: u64,
+    pub max_attempts: usize,
+}
+impl Default for ReportRetryData {
+    fn default() -> Self {
+        Self {
+            max_pending: 256,
+            initial_delay_millisecond: 1000,
+            max_delay_millisecond: 30000,
+            max_attempts: 5,
+        }
+    }
+}
+
+/// how a terminal connects again after its connection is lost.
+/// the delay doubles after each attempt, up to the max, and varies by the jitter,
+/// a fraction of it. max_attempts 0 means no limit.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct ReconnectData {
+    pub enabled: bool,
+    pub initial_delay_millisecond: u64,
+    pub max_delay_millisecond: u64,
+    pub jitter: f64,
+    pub max_attempts: usize,
+}
+impl Default for ReconnectData {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            initial_delay_millisecond: 500,
+            max_delay_millisecond: 30000,
+            jitter: 0.2,
+            max_attempts: 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct TlsClientData {
+    pub ca: String,
+    pub domain: String,
+    pub cert: Option<String>,
+    pub key: Option<String>,
+}
+
 #[cfg_attr(test, derive(Debug))]
 #[derive(serde::Serialize, serde::Deserialize)]
 pub struct Client {
@@ -140,11 +312,58 @@ pub struct Client {
     pub token_count_by_terminal: usize,
     pub callback_count_by_terminal: usize,
     pub invoke_timeout_in_terminal: u64,
+    #[serde(default)]
+    pub tls: Option<TlsClientData>,
+    #[serde(default = "Client::default_heartbeat_interval")]
+    pub heartbeat_interval_millisecond: u64,
+    #[serde(default = "Client::default_idle_timeout")]
+    pub idle_timeout_millisecond: u64,
+    #[serde(default = "Client::default_stream_credit")]
+    pub stream_credit_by_terminal: usize,
+    #[serde(default = "Client::default_queue_capacity")]
+    pub queue_capacity_by_terminal: usize,
+    #[serde(default)]
+    pub overflow_policy: OverflowPolicy,
+    #[serde(default)]
+    pub limits: LimitData,
+    #[serde(default = "Client::default_serializers")]
+    pub serializers: Vec<String>,
+    #[serde(default = "Client::default_compressions")]
+    pub compressions: Vec<String>,
+    #[serde(default = "Client::default_compression_threshold")]
+    pub compression_threshold: usize,
+    #[serde(default)]
+    pub identity: Option<String>,
+    #[serde(default)]
+    pub report_retry: ReportRetryData,
+    #[serde(default)]
+    pub reconnect: ReconnectData,
 }
 impl Client {
     fn file_name() -> &'static str {
         "./client.json"
     }
+    fn default_heartbeat_interval() -> u64 {
+        10000
+    }
+    fn default_idle_timeout() -> u64 {
+        30000
+    }
+    fn default_stream_credit() -> usize {
+        16
+    }
+    fn default_queue_capacity() -> usize {
+        1024
+    }
+    fn default_serializers() -> Vec<String> {
+        vec!["bincode".to_string()]
+    }
+    fn default_compressions() -> Vec<String> {
+        vec!["none".to_string()]
+    }
+    fn default_compression_threshold() -> usize {
+        1024
+    }
     pub fn load() -> Self {
         let json_str = read_json_str(Self::file_name());
         match serde_json::from_str(&json_str) {
@@ -157,6 +376,19 @@ impl Client {
                     token_count_by_terminal: 2,
                     callback_count_by_terminal: 2,
                     invoke_timeout_in_terminal: 5000,
+                    tls: None,
+                    heartbeat_interval_millisecond: Self::default_heartbeat_interval(),
+                    idle_timeout_millisecond: Self::default_idle_timeout(),
+                    stream_credit_by_terminal: Self::default_stream_credit(),
+                    queue_capacity_by_terminal: Self::default_queue_capacity(),
+                    overflow_policy: OverflowPolicy::default(),
+                    limits: LimitData::default(),
+                    serializers: Self::default_serializers(),
+                    compressions: Self::default_compressions(),
+                    compression_threshold: Self::default_compression_threshold(),
+                    identity: None,
+                    report_retry: ReportRetryData::default(),
+                    reconnect: ReconnectData::default(),
                 }
             }
         }
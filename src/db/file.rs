@@ -0,0 +1,75 @@
+// -- file.rs --
+
+use {
+    crate::{
+        freeze::Storage,
+        servant::{Oid, ServantResult}
+    },
+    std::{
+        fs,
+        path::PathBuf,
+    },
+};
+
+// --
+
+pub struct FileDb(PathBuf);
+
+impl FileDb {
+    pub fn new(dir: &str) -> ServantResult<Self> {
+        fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+        Ok(Self(PathBuf::from(dir)))
+    }
+    fn escape(s: &str) -> String {
+        let mut r = String::with_capacity(s.len());
+        for b in s.bytes() {
+            if b.is_ascii_alphanumeric() || b == b'_' || b == b'-' {
+                r.push(b as char);
+            } else {
+                r.push_str(&format!("%{:02X}", b));
+            }
+        }
+        r
+    }
+    fn path(&self, oid: &Oid) -> PathBuf {
+        self.0.join(format!(
+            "{}.{}",
+            Self::escape(oid.category()),
+            Self::escape(oid.name())
+        ))
+    }
+}
+
+impl Storage for FileDb {
+    fn store(&mut self, oid: &Oid, bytes: &[u8]) -> ServantResult<()> {
+        let path = self.path(oid);
+        let mut tmp = path.clone().into_os_string();
+        tmp.push(".tmp");
+        fs::write(&tmp, bytes).map_err(|e| e.to_string())?;
+        fs::rename(&tmp, &path).map_err(|e| e.to_string())?;
+        Ok(())
+    }
+    fn load(&mut self, oid: &Oid) -> ServantResult<Vec<u8>> {
+        fs::read(self.path(oid)).map_err(|e| format!("{} load error: {}", oid, e).into())
+    }
+}
+
+// --
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn store_and_load() {
+        let dir = std::env::temp_dir().join("servant_file_db_test");
+        let _ = fs::remove_dir_all(&dir);
+        let mut db = FileDb::new(dir.to_str().unwrap()).unwrap();
+        let oid = Oid::new("a/b.c", "Hello");
+        assert!(db.load(&oid).is_err());
+        db.store(&oid, &[1, 2, 3]).unwrap();
+        assert_eq!(db.load(&oid).unwrap(), vec![1, 2, 3]);
+        assert!(db.load(&Oid::new("a/b", "Hello")).is_err());
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
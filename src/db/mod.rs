@@ -1,7 +1,8 @@
 // -- mod.rs --
 
+mod file;
 mod sqlite;
 
 // --
 
-pub use sqlite::SqliteDb;
\ No newline at end of file
+pub use {file::FileDb, sqlite::SqliteDb};
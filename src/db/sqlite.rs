@@ -5,24 +5,73 @@ use {
         freeze::Storage,
         servant::{Oid, ServantResult}
     },
+    rusqlite::{params, Connection, OptionalExtension},
 };
 
 // --
 
-pub struct SqliteDb;
+pub struct SqliteDb(Connection);
 
 impl SqliteDb {
-    #[allow(unused)]
-    pub fn new(_fn: &str) -> Self {
-        Self
+    pub fn new(file_name: &str) -> ServantResult<Self> {
+        let conn = Connection::open(file_name).map_err(|e| e.to_string())?;
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS freeze (
+                name TEXT NOT NULL,
+                category TEXT NOT NULL,
+                dump BLOB NOT NULL,
+                PRIMARY KEY (name, category)
+            )",
+            params![],
+        )
+        .map_err(|e| e.to_string())?;
+        Ok(Self(conn))
     }
 }
 
 impl Storage for SqliteDb {
-    fn store(&mut self, _oid: &Oid, _bytes: &[u8]) -> ServantResult<()> {
-        unimplemented!();
+    fn store(&mut self, oid: &Oid, bytes: &[u8]) -> ServantResult<()> {
+        self.0
+            .execute(
+                "INSERT OR REPLACE INTO freeze (name, category, dump) VALUES (?1, ?2, ?3)",
+                params![oid.name(), oid.category(), bytes],
+            )
+            .map_err(|e| e.to_string())?;
+        Ok(())
     }
-    fn load(&mut self, _oid: &Oid) -> ServantResult<Vec<u8>> {
-        unimplemented!();
+    fn load(&mut self, oid: &Oid) -> ServantResult<Vec<u8>> {
+        self.0
+            .query_row(
+                "SELECT dump FROM freeze WHERE name = ?1 AND category = ?2",
+                params![oid.name(), oid.category()],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(|e| e.to_string())?
+            .ok_or(format!("{} dosen't exist in db.", oid).into())
     }
-}
\ No newline at end of file
+}
+
+// --
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn store_and_load() {
+        let file_name = std::env::temp_dir().join("servant_sqlite_db_test.db");
+        let _ = std::fs::remove_file(&file_name);
+        let oid = Oid::new("s1", "Hello");
+        {
+            let mut db = SqliteDb::new(file_name.to_str().unwrap()).unwrap();
+            assert!(db.load(&oid).is_err());
+            db.store(&oid, &[1, 2, 3]).unwrap();
+            db.store(&oid, &[4, 5]).unwrap();
+        }
+        let mut db = SqliteDb::new(file_name.to_str().unwrap()).unwrap();
+        assert_eq!(db.load(&oid).unwrap(), vec![4, 5]);
+        assert!(db.load(&Oid::new("s1", "World")).is_err());
+        let _ = std::fs::remove_file(&file_name);
+    }
+}
@@ -0,0 +1,235 @@
+// -- durable.rs --
+
+use {
+    crate::{
+        config::DurableNoticeData,
+        servant::{ConnectionId, Record},
+    },
+    std::{
+        collections::{HashMap, VecDeque},
+        time::{SystemTime, UNIX_EPOCH},
+    },
+};
+
+// --
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+struct Entry {
+    id: usize,
+    time: u64,
+    record: Record,
+}
+
+struct _Identity {
+    entries: VecDeque<Entry>,
+    addr: Option<ConnectionId>,
+    // the notices are sent to the connection as they come, after the replay.
+    live: bool,
+    // when it was unbound, an identity away longer than the max age is forgotten.
+    since: u64,
+}
+
+/// the notices not yet acknowledged by every terminal identity,
+/// replayed when the identity resumes on a new connection.
+pub(crate) struct DurableNotices {
+    identities: HashMap<String, _Identity>,
+    max_count: usize,
+    max_age: u64,
+}
+impl DurableNotices {
+    pub(crate) fn new(data: &DurableNoticeData) -> Self {
+        Self {
+            identities: HashMap::new(),
+            max_count: data.max_count.max(1),
+            max_age: data.max_age_second,
+        }
+    }
+    pub(crate) fn contains(&self, identity: &str) -> bool {
+        self.identities.contains_key(identity)
+    }
+    /// the connections whose identities are being replayed, the new notices wait
+    /// in the buffers for them.
+    pub(crate) fn replaying(&self) -> Vec<ConnectionId> {
+        self.identities
+            .values()
+            .filter(|i| !i.live)
+            .filter_map(|i| i.addr.clone())
+            .collect()
+    }
+    /// keeps the notice for every identity, returns the identities.
+    pub(crate) fn push(&mut self, id: usize, record: &Record) -> Vec<String> {
+        let now = now();
+        let (max_count, max_age) = (self.max_count, self.max_age);
+        self.identities
+            .retain(|_, i| i.addr.is_some() || now.saturating_sub(i.since) <= max_age);
+        self.identities
+            .iter_mut()
+            .map(|(identity, i)| {
+                i.entries.push_back(Entry {
+                    id,
+                    time: now,
+                    record: record.clone(),
+                });
+                while i.entries.len() > max_count {
+                    i.entries.pop_front();
+                }
+                while let Some(e) = i.entries.front() {
+                    if now.saturating_sub(e.time) <= max_age {
+                        break;
+                    }
+                    i.entries.pop_front();
+                }
+                identity.clone()
+            })
+            .collect()
+    }
+    /// binds the identity to the connection to be replayed, and drops the notices
+    /// till `last_id`.
+    pub(crate) fn resume(&mut self, identity: &str, addr: &ConnectionId, last_id: usize) {
+        self.unbind(addr);
+        let now = now();
+        let max_age = self.max_age;
+        let i = self
+            .identities
+            .entry(identity.to_string())
+            .or_insert_with(|| _Identity {
+                entries: VecDeque::new(),
+                addr: None,
+                live: false,
+                since: now,
+            });
+        i.addr.replace(addr.clone());
+        i.live = false;
+        i.entries
+            .retain(|e| e.id > last_id && now.saturating_sub(e.time) <= max_age);
+    }
+    /// the notices after `last_id` with their ids, to be replayed.
+    pub(crate) fn pending(&self, identity: &str, last_id: usize) -> Vec<(usize, Record)> {
+        self.identities
+            .get(identity)
+            .map(|i| {
+                i.entries
+                    .iter()
+                    .filter(|e| e.id > last_id)
+                    .map(|e| (e.id, e.record.clone()))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+    /// the replay is done, if the identity is still bound to the connection.
+    pub(crate) fn set_live(&mut self, identity: &str, addr: &ConnectionId) {
+        if let Some(i) = self.identities.get_mut(identity) {
+            if i.addr.as_ref() == Some(addr) {
+                i.live = true;
+            }
+        }
+    }
+    /// drops the notices of the identity bound to the connection till `id`,
+    /// returns the identity.
+    pub(crate) fn ack(&mut self, addr: &ConnectionId, id: usize) -> Option<String> {
+        self.identities
+            .iter_mut()
+            .find(|(_, i)| i.addr.as_ref() == Some(addr))
+            .map(|(identity, i)| {
+                i.entries.retain(|e| e.id > id);
+                identity.clone()
+            })
+    }
+    pub(crate) fn unbind(&mut self, addr: &ConnectionId) {
+        let now = now();
+        self.identities
+            .values_mut()
+            .filter(|i| i.addr.as_ref() == Some(addr))
+            .for_each(|i| {
+                i.addr.take();
+                i.live = false;
+                i.since = now;
+            });
+    }
+    pub(crate) fn max_id(&self, identity: &str) -> usize {
+        self.identities
+            .get(identity)
+            .and_then(|i| i.entries.back())
+            .map(|e| e.id)
+            .unwrap_or(0)
+    }
+    pub(crate) fn dump(&self, identity: &str) -> Option<Vec<u8>> {
+        let i = self.identities.get(identity)?;
+        match bincode::serialize(&i.entries) {
+            Ok(bytes) => Some(bytes),
+            Err(e) => {
+                log::warn!("durable notices of {}: {}", identity, e.to_string());
+                None
+            }
+        }
+    }
+    /// the notices kept before the server was restarted.
+    pub(crate) fn load(&mut self, identity: &str, bytes: &[u8]) {
+        match bincode::deserialize::<VecDeque<Entry>>(bytes) {
+            Ok(entries) => {
+                self.identities.insert(
+                    identity.to_string(),
+                    _Identity {
+                        entries,
+                        addr: None,
+                        live: false,
+                        since: now(),
+                    },
+                );
+            }
+            Err(e) => log::warn!("durable notices of {}: {}", identity, e.to_string()),
+        }
+    }
+}
+
+// --
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn notice(id: usize) -> Record {
+        Record::Notice { id, msg: Vec::new() }
+    }
+    fn ids(records: Vec<(usize, Record)>) -> Vec<usize> {
+        records.into_iter().map(|(id, _)| id).collect()
+    }
+
+    #[test]
+    fn durable_notices_test() {
+        let mut d = DurableNotices::new(&DurableNoticeData {
+            max_count: 3,
+            max_age_second: 3600,
+            persist: false,
+        });
+        let a = ConnectionId::Local(1);
+        d.resume("t1", &a, 0);
+        d.set_live("t1", &a);
+        for id in 1..=4 {
+            d.push(id, &notice(id));
+        }
+        assert_eq!(d.ack(&a, 2).as_deref(), Some("t1"));
+        d.unbind(&a);
+        assert_eq!(d.ack(&a, 3), None);
+        let b = ConnectionId::Local(2);
+        d.resume("t1", &b, 0);
+        assert_eq!(ids(d.pending("t1", 0)), vec![3, 4]);
+        assert_eq!(d.replaying(), vec![b.clone()]);
+        d.set_live("t1", &b);
+        assert!(d.replaying().is_empty());
+
+        let bytes = d.dump("t1").unwrap();
+        let mut d2 = DurableNotices::new(&DurableNoticeData::default());
+        d2.load("t1", &bytes);
+        assert_eq!(d2.max_id("t1"), 4);
+        d2.resume("t1", &a, 3);
+        assert_eq!(ids(d2.pending("t1", 3)), vec![4]);
+    }
+}
@@ -0,0 +1,100 @@
+// -- format.rs --
+
+use serde::{de::DeserializeOwned, Serialize};
+
+// --
+
+/// the serialization format of the records and the results on a connection.
+/// the handshake is always in bincode, the format negotiated there is used after it.
+/// the arguments are in it only for the interfaces written by hand, see
+/// `Terminal::invoke_with_format` and `Context::format`. the interfaces generated by
+/// the macros keep their arguments in bincode on every connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum Format {
+    Bincode,
+    Json,
+    MessagePack,
+    Cbor,
+}
+impl Default for Format {
+    fn default() -> Self {
+        Self::Bincode
+    }
+}
+
+impl Format {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Bincode => "bincode",
+            Self::Json => "json",
+            Self::MessagePack => "msgpack",
+            Self::Cbor => "cbor",
+        }
+    }
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "bincode" => Some(Self::Bincode),
+            "json" => Some(Self::Json),
+            "msgpack" => Some(Self::MessagePack),
+            "cbor" => Some(Self::Cbor),
+            _ => None,
+        }
+    }
+    /// the formats built in, depending on the features `msgpack` and `cbor`.
+    pub fn supported() -> Vec<&'static str> {
+        let mut v = vec![Self::Bincode.name(), Self::Json.name()];
+        if cfg!(feature = "msgpack") {
+            v.push(Self::MessagePack.name());
+        }
+        if cfg!(feature = "cbor") {
+            v.push(Self::Cbor.name());
+        }
+        v
+    }
+    pub fn serialize<T: Serialize>(&self, value: &T) -> Result<Vec<u8>, String> {
+        match self {
+            Self::Bincode => bincode::serialize(value).map_err(|e| e.to_string()),
+            Self::Json => serde_json::to_vec(value).map_err(|e| e.to_string()),
+            #[cfg(feature = "msgpack")]
+            Self::MessagePack => rmp_serde::to_vec_named(value).map_err(|e| e.to_string()),
+            #[cfg(feature = "cbor")]
+            Self::Cbor => serde_cbor::to_vec(value).map_err(|e| e.to_string()),
+            #[allow(unreachable_patterns)]
+            _ => Err(self.disabled()),
+        }
+    }
+    pub fn deserialize<T: DeserializeOwned>(&self, buf: &[u8]) -> Result<T, String> {
+        match self {
+            Self::Bincode => bincode::deserialize(buf).map_err(|e| e.to_string()),
+            Self::Json => serde_json::from_slice(buf).map_err(|e| e.to_string()),
+            #[cfg(feature = "msgpack")]
+            Self::MessagePack => rmp_serde::from_slice(buf).map_err(|e| e.to_string()),
+            #[cfg(feature = "cbor")]
+            Self::Cbor => serde_cbor::from_slice(buf).map_err(|e| e.to_string()),
+            #[allow(unreachable_patterns)]
+            _ => Err(self.disabled()),
+        }
+    }
+    fn disabled(&self) -> String {
+        format!("the {} feature is disabled.", self.name())
+    }
+}
+
+// --
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_test() {
+        let value: Result<Vec<u8>, String> = Ok(vec![1, 2, 3]);
+        for name in Format::supported() {
+            let f = Format::from_name(name).unwrap();
+            assert_eq!(f.name(), name);
+            let buf = f.serialize(&value).unwrap();
+            assert_eq!(f.deserialize::<Result<Vec<u8>, String>>(&buf).unwrap(), value);
+        }
+        assert!(Format::from_name("xml").is_none());
+    }
+}
@@ -59,6 +59,10 @@ impl Freeze {
     pub fn store(&mut self, oid: &Oid, bytes: &[u8]) -> ServantResult<()> {
         self.db.store(oid, bytes)
     }
+    // the bytes as they are stored, for the data which isn't a servant.
+    pub(crate) fn load_bytes(&mut self, oid: &Oid) -> ServantResult<Vec<u8>> {
+        self.db.load(oid)
+    }
     pub fn load(&mut self, oid: &Oid) -> Option<ServantEntity> {
         let category = oid.category();
         match self.db.load(oid) {
@@ -0,0 +1,101 @@
+// -- handshake.rs --
+
+use crate::servant::Record;
+
+// --
+
+/// bumped whenever `Record` changes incompatibly.
+pub const PROTOCOL_VERSION: u32 = 8;
+
+pub(crate) fn version() -> String {
+    env!("CARGO_PKG_VERSION").to_string()
+}
+
+cfg_client! {
+    // the serializers and compressions are offered in the order of preference.
+    pub(crate) fn hello(
+        auth: Option<Vec<u8>>,
+        serializers: &[String],
+        compressions: &[String],
+    ) -> Record {
+        Record::Hello {
+            protocol: PROTOCOL_VERSION,
+            version: version(),
+            serializers: serializers.to_vec(),
+            compressions: compressions.to_vec(),
+            auth,
+        }
+    }
+}
+
+cfg_server! {
+    use crate::{compress::Compression, format::Format};
+
+    // the first one offered by the terminal and supported here wins.
+    fn choose(offered: &[String], supported: &[&str]) -> Option<String> {
+        offered
+            .iter()
+            .find(|x| supported.contains(&x.as_str()))
+            .map(|x| x.clone())
+    }
+
+    pub(crate) fn negotiate(
+        protocol: u32,
+        serializers: &[String],
+        compressions: &[String],
+    ) -> Result<Record, String> {
+        if protocol != PROTOCOL_VERSION {
+            return Err(format!(
+                "protocol version {} isn't supported, server protocol version is {}.",
+                protocol, PROTOCOL_VERSION
+            ));
+        }
+        let supported = Format::supported();
+        let serializer = choose(serializers, &supported).ok_or_else(|| {
+            format!(
+                "none of serializers {:?} is supported, server supports {:?}.",
+                serializers, supported
+            )
+        })?;
+        let supported = Compression::supported();
+        let compression = choose(compressions, &supported).ok_or_else(|| {
+            format!(
+                "none of compressions {:?} is supported, server supports {:?}.",
+                compressions, supported
+            )
+        })?;
+        Ok(Record::Welcome {
+            protocol: PROTOCOL_VERSION,
+            version: version(),
+            serializer,
+            compression,
+        })
+    }
+}
+
+// --
+
+#[cfg(all(test, feature = "server"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn negotiate_test() {
+        let offered = vec!["xml".to_string(), "bincode".to_string()];
+        let none = vec!["none".to_string()];
+        match negotiate(PROTOCOL_VERSION, &offered, &none) {
+            Ok(Record::Welcome { serializer, compression, .. }) => {
+                assert_eq!(serializer, "bincode");
+                assert_eq!(compression, "none");
+            }
+            r => panic!("unexpected: {:?}", r),
+        }
+        let json = vec!["json".to_string(), "bincode".to_string()];
+        match negotiate(PROTOCOL_VERSION, &json, &none) {
+            Ok(Record::Welcome { serializer, .. }) => assert_eq!(serializer, "json"),
+            r => panic!("unexpected: {:?}", r),
+        }
+        assert!(negotiate(PROTOCOL_VERSION + 1, &offered, &none).is_err());
+        assert!(negotiate(PROTOCOL_VERSION, &none, &none).is_err());
+    }
+}
@@ -23,32 +23,59 @@ cfg_server_or_client! {
     pub use servant_macro::watch_interface;
     pub use servant_macro::report_interface;
     pub use servant_macro::notify_interface;
+    pub use async_trait::async_trait;
 
+    mod compress;
     mod config;
+    mod format;
+    mod handshake;
+    mod queue;
     mod servant;
+    mod transport;
     mod sync;
     mod task;
 
     pub use crate::servant::{
-        Context, NotifyServant, Oid, ReportServant, Servant, ServantError,
-        ServantResult, UserCookie, WatchServant,
+        AsyncReportServant, AsyncServant, AsyncWatchServant, CancelHandle, ConnectionId, Context,
+        NotifyServant, Oid, ReportServant, Servant, ServantError, ServantResult, StreamServant,
+        UploadServant, UserCookie, WatchServant,
     };
+    pub use crate::compress::{Compression, CompressionStats};
+    pub use crate::format::Format;
+    pub use crate::handshake::PROTOCOL_VERSION;
+    pub use crate::queue::{OverflowPolicy, QueueStats};
+    pub use crate::transport::{Connection, Connector, Listener, RecordReader, RecordWriter};
 }
 
 // --
 
 cfg_server! {
     mod db;
+    mod durable;
     mod freeze;
     mod adapter;
     mod server;
-    pub use {adapter::AdapterRegister, server::Server, crate:: servant::ServantRegister};
+    pub use {
+        adapter::{AdapterRegister, Authenticator, IdentityAuthenticator},
+        db::{FileDb, SqliteDb},
+        freeze::{MemoryDb, Storage},
+        server::Server,
+        crate::servant::ServantRegister,
+    };
 }
 
 cfg_client! {
     mod client;
     mod terminal;
-    pub use {client::Client, terminal::Terminal};
+    pub use {
+        client::Client,
+        terminal::{ConnectionState, ResponseStream, Terminal},
+    };
+}
+
+cfg_server_and_client! {
+    mod loopback;
+    pub use loopback::Loopback;
 }
 
 cfg_help_entity! {
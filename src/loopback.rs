@@ -0,0 +1,93 @@
+// -- loopback.rs --
+
+use {
+    crate::{
+        adapter::{Adapter, AdapterRegister},
+        client::Client,
+        server::Server,
+        servant::{ConnectionId, Record, ServantRegister, ServantResult},
+        terminal::Terminal,
+        transport::{Connection, Connector},
+    },
+    async_std::task,
+    async_trait::async_trait,
+    futures::{channel::mpsc::channel, sink::SinkExt, stream::StreamExt},
+    log::{info, warn},
+    std::{
+        io,
+        sync::atomic::{AtomicUsize, Ordering},
+    },
+};
+
+// --
+
+static SEQ: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Clone)]
+pub struct Loopback {
+    ar: AdapterRegister,
+    sr: ServantRegister,
+    serve_count: usize,
+    max_count_of_connection: usize,
+    queue_capacity: usize,
+}
+
+#[async_trait]
+impl Connector for Loopback {
+    async fn connect(&self) -> io::Result<Connection> {
+        if self.ar.is_closing().await {
+            return Err(io::Error::new(
+                io::ErrorKind::ConnectionRefused,
+                "server is shutting down.",
+            ));
+        }
+        if !self.ar.reserve(self.max_count_of_connection).await {
+            warn!("too many connections. refuse the loopback connection.");
+            return Err(io::Error::new(
+                io::ErrorKind::ConnectionRefused,
+                "too many connections.",
+            ));
+        }
+        let id = ConnectionId::Local(SEQ.fetch_add(1, Ordering::Relaxed) + 1);
+        // the channels stand for the socket buffers, they're bounded as well.
+        let (to_adapter, from_terminal) = channel::<Record>(self.queue_capacity);
+        let (to_terminal, from_adapter) = channel::<Record>(self.queue_capacity);
+
+        let adapter = Adapter::new(self.ar.clone(), self.sr.clone(), self.serve_count);
+        let conn = Connection::new(
+            id.clone(),
+            from_terminal.map(Ok::<Record, io::Error>).boxed(),
+            Box::pin(to_terminal.sink_map_err(|e| io::Error::new(io::ErrorKind::BrokenPipe, e))),
+        );
+        task::spawn(async move {
+            let r = adapter.run(conn).await;
+            info!("loopback adapter run result: {:?}", r);
+        });
+
+        Ok(Connection::new(
+            id,
+            from_adapter.map(Ok::<Record, io::Error>).boxed(),
+            Box::pin(to_adapter.sink_map_err(|e| io::Error::new(io::ErrorKind::BrokenPipe, e))),
+        ))
+    }
+}
+
+// --
+
+impl<T: Clone> Server<T> {
+    pub fn loopback(&self) -> Loopback {
+        Loopback {
+            ar: self.adapter_register(),
+            sr: self.servant_register(),
+            serve_count: self.config().serve_count_by_adapter,
+            max_count_of_connection: self.config().max_count_of_connection,
+            queue_capacity: self.config().queue_capacity_by_adapter,
+        }
+    }
+}
+
+impl Client {
+    pub async fn connect_loopback(&self, lb: &Loopback) -> ServantResult<Terminal> {
+        self.connect_with("loopback".to_string(), lb.clone()).await
+    }
+}
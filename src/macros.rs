@@ -33,6 +33,15 @@ macro_rules! cfg_server_or_client {
     }
 }
 
+macro_rules! cfg_server_and_client {
+    ($($item:item)*) => {
+        $(
+            #[cfg(all(feature = "server", feature = "client"))]
+            $item
+        )*
+    }
+}
+
 macro_rules! cfg_help_entity {
     ($($item:item)*) => {
         $(
@@ -59,3 +68,23 @@ macro_rules! cfg_admin_entity {
         )*
     }
 }
+
+macro_rules! cfg_tls {
+    ($($item:item)*) => {
+        $(
+            #[cfg(feature = "tls")]
+            #[cfg_attr(docsrs, doc(cfg(feature = "tls")))]
+            $item
+        )*
+    }
+}
+
+macro_rules! cfg_websocket {
+    ($($item:item)*) => {
+        $(
+            #[cfg(feature = "websocket")]
+            #[cfg_attr(docsrs, doc(cfg(feature = "websocket")))]
+            $item
+        )*
+    }
+}
@@ -0,0 +1,241 @@
+// -- queue.rs --
+
+use {
+    crate::servant::Record,
+    futures::{future::poll_fn, stream::Stream},
+    std::{
+        collections::VecDeque,
+        io,
+        pin::Pin,
+        sync::{Arc, Mutex},
+        task::{Context, Poll, Waker},
+    },
+};
+
+// --
+
+/// what a full queue does with one more record.
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum OverflowPolicy {
+    /// the sender waits till the queue has room.
+    Block,
+    /// the oldest notice in the queue, or the new one, is dropped, on a topic or not.
+    /// other records wait as `Block`.
+    DropOldestNotice,
+    /// the queue is closed, and the connection with it.
+    Disconnect,
+}
+impl Default for OverflowPolicy {
+    fn default() -> Self {
+        Self::Block
+    }
+}
+
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
+pub struct QueueStats {
+    pub depth: usize,
+    pub capacity: usize,
+    pub peak: usize,
+    pub dropped: usize,
+}
+
+struct _Queue {
+    records: VecDeque<Record>,
+    capacity: usize,
+    policy: OverflowPolicy,
+    closed: bool,
+    rx_waker: Option<Waker>,
+    tx_wakers: Vec<Waker>,
+    peak: usize,
+    dropped: usize,
+}
+impl _Queue {
+    fn push_back(&mut self, record: Record) {
+        self.records.push_back(record);
+        self.peak = self.peak.max(self.records.len());
+        if let Some(w) = self.rx_waker.take() {
+            w.wake();
+        }
+    }
+    fn close(&mut self) {
+        self.closed = true;
+        if let Some(w) = self.rx_waker.take() {
+            w.wake();
+        }
+        self.tx_wakers.drain(..).for_each(|w| w.wake());
+    }
+}
+
+/// the bounded queue of records to be written to a connection.
+#[derive(Clone)]
+pub(crate) struct RecordQueue(Arc<Mutex<_Queue>>);
+impl RecordQueue {
+    pub(crate) fn new(capacity: usize, policy: OverflowPolicy) -> Self {
+        Self(Arc::new(Mutex::new(_Queue {
+            records: VecDeque::new(),
+            capacity: capacity.max(1),
+            policy,
+            closed: false,
+            rx_waker: None,
+            tx_wakers: Vec::new(),
+            peak: 0,
+            dropped: 0,
+        })))
+    }
+    pub(crate) async fn push(&self, record: Record) -> io::Result<()> {
+        let mut record = Some(record);
+        poll_fn(|cx| {
+            let mut g = self.0.lock().unwrap();
+            if g.closed {
+                return Poll::Ready(Err(closed()));
+            }
+            if g.records.len() < g.capacity {
+                g.push_back(record.take().unwrap());
+                return Poll::Ready(Ok(()));
+            }
+            match g.policy {
+                OverflowPolicy::Block => {}
+                OverflowPolicy::DropOldestNotice => {
+                    let oldest = g
+                        .records
+                        .iter()
+                        .position(is_notice);
+                    if let Some(i) = oldest {
+                        g.records.remove(i);
+                        g.dropped += 1;
+                        g.push_back(record.take().unwrap());
+                        return Poll::Ready(Ok(()));
+                    }
+                    if record.as_ref().map_or(false, is_notice) {
+                        g.dropped += 1;
+                        return Poll::Ready(Ok(()));
+                    }
+                }
+                OverflowPolicy::Disconnect => {
+                    g.records.clear();
+                    g.close();
+                    return Poll::Ready(Err(io::Error::new(
+                        io::ErrorKind::Other,
+                        "queue is overflowed, the connection is closed.",
+                    )));
+                }
+            }
+            g.tx_wakers.push(cx.waker().clone());
+            Poll::Pending
+        })
+        .await
+    }
+    /// pushes regardless of the capacity, for the small records that keep
+    /// the protocol going, such as credits and cancels.
+    pub(crate) fn force(&self, record: Record) -> io::Result<()> {
+        let mut g = self.0.lock().unwrap();
+        if g.closed {
+            return Err(closed());
+        }
+        g.push_back(record);
+        Ok(())
+    }
+    /// the receiver gets the records left in the queue, then the end.
+    pub(crate) fn close(&self) {
+        self.0.lock().unwrap().close();
+    }
+    pub(crate) fn set_policy(&self, policy: OverflowPolicy) {
+        self.0.lock().unwrap().policy = policy;
+    }
+    pub(crate) fn stats(&self) -> QueueStats {
+        let g = self.0.lock().unwrap();
+        QueueStats {
+            depth: g.records.len(),
+            capacity: g.capacity,
+            peak: g.peak,
+            dropped: g.dropped,
+        }
+    }
+    pub(crate) fn receiver(&self) -> RecordReceiver {
+        RecordReceiver(self.clone())
+    }
+}
+
+fn is_notice(record: &Record) -> bool {
+    matches!(record, Record::Notice { .. } | Record::TopicNotice { .. })
+}
+
+fn closed() -> io::Error {
+    io::Error::new(io::ErrorKind::BrokenPipe, "queue is closed.")
+}
+
+pub(crate) struct RecordReceiver(RecordQueue);
+impl Stream for RecordReceiver {
+    type Item = Record;
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut g = (self.0).0.lock().unwrap();
+        if let Some(record) = g.records.pop_front() {
+            g.tx_wakers.drain(..).for_each(|w| w.wake());
+            Poll::Ready(Some(record))
+        } else if g.closed {
+            Poll::Ready(None)
+        } else {
+            g.rx_waker.replace(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+}
+
+// --
+
+#[cfg(test)]
+mod tests {
+    use {super::*, async_std::task, futures::stream::StreamExt};
+
+    fn notice(id: usize) -> Record {
+        Record::Notice { id, msg: Vec::new() }
+    }
+
+    #[test]
+    fn drop_oldest_notice_test() {
+        task::block_on(async {
+            let q = RecordQueue::new(2, OverflowPolicy::DropOldestNotice);
+            for id in 1..=3 {
+                q.push(notice(id)).await.unwrap();
+            }
+            let stats = q.stats();
+            assert_eq!((stats.depth, stats.dropped, stats.peak), (2, 1, 2));
+            q.close();
+            let ids: Vec<usize> = q
+                .receiver()
+                .map(|r| match r {
+                    Record::Notice { id, .. } => id,
+                    _ => 0,
+                })
+                .collect()
+                .await;
+            assert_eq!(ids, vec![2, 3]);
+        });
+    }
+
+    #[test]
+    fn drop_topic_notice_test() {
+        task::block_on(async {
+            let q = RecordQueue::new(1, OverflowPolicy::DropOldestNotice);
+            q.push(Record::Ping { id: 1 }).await.unwrap();
+            let topic_notice = Record::TopicNotice {
+                id: 2,
+                topic: "prices".to_string(),
+                msg: Vec::new(),
+            };
+            q.push(topic_notice).await.unwrap();
+            assert_eq!((q.stats().depth, q.stats().dropped), (1, 1));
+        });
+    }
+
+    #[test]
+    fn disconnect_test() {
+        task::block_on(async {
+            let q = RecordQueue::new(1, OverflowPolicy::Disconnect);
+            q.push(notice(1)).await.unwrap();
+            assert!(q.push(notice(2)).await.is_err());
+            assert!(q.force(notice(3)).is_err());
+            assert_eq!(q.receiver().next().await.is_none(), true);
+        });
+    }
+}
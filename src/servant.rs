@@ -1,9 +1,16 @@
 // -- servant.rs --
 
 use {
-    crate::utilities::BoolToOption,
+    crate::{format::Format, utilities::BoolToOption},
+    async_trait::async_trait,
+    futures::{channel::oneshot, stream::BoxStream},
     serde::{Deserialize, Serialize},
-    std::{collections::HashMap, error::Error, net::SocketAddr},
+    std::{
+        collections::HashMap,
+        error::Error,
+        net::SocketAddr,
+        time::{Duration, Instant},
+    },
 };
 
 // --
@@ -13,6 +20,7 @@ pub enum ServantError {
     NoSupportSerializable,
     DuplicateOid,
     Other(String),
+    Timeout,
 }
 
 impl Error for ServantError {}
@@ -63,7 +71,82 @@ impl std::fmt::Display for Oid {
 // --
 
 pub type UserCookie = usize;
-pub type ConnectionId = SocketAddr;
+
+#[derive(Clone, Debug, Eq, PartialEq, Hash, serde::Serialize, serde::Deserialize)]
+pub enum ConnectionId {
+    Tcp(SocketAddr),
+    // path of the socket file, and the sequence assigned by the listener.
+    // the sequence is 0 on the terminal side.
+    Unix(String, usize),
+    // in-process loopback connection.
+    Local(usize),
+    WebSocket(SocketAddr),
+}
+
+impl From<SocketAddr> for ConnectionId {
+    fn from(addr: SocketAddr) -> Self {
+        Self::Tcp(addr)
+    }
+}
+
+impl std::fmt::Display for ConnectionId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Tcp(addr) => write!(f, "{}", addr),
+            Self::Unix(path, seq) => write!(f, "unix:{}#{}", path, seq),
+            Self::Local(seq) => write!(f, "loopback#{}", seq),
+            Self::WebSocket(addr) => write!(f, "ws://{}", addr),
+        }
+    }
+}
+
+#[derive(Default)]
+struct _CancelHandle {
+    cancelled: bool,
+    waiters: Vec<oneshot::Sender<()>>,
+}
+
+/// shared by all clones. on the terminal side it cancels an invoke,
+/// on the server side it tells the servant that the terminal gave up.
+#[derive(Clone, Default)]
+pub struct CancelHandle(std::sync::Arc<std::sync::Mutex<_CancelHandle>>);
+impl CancelHandle {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn cancel(&self) {
+        let waiters = {
+            let mut g = self.0.lock().unwrap();
+            g.cancelled = true;
+            std::mem::take(&mut g.waiters)
+        };
+        waiters.into_iter().for_each(|tx| {
+            let _ = tx.send(());
+        });
+    }
+    pub fn is_cancelled(&self) -> bool {
+        self.0.lock().unwrap().cancelled
+    }
+    /// resolves when the handle is cancelled.
+    pub async fn cancelled(&self) {
+        let rx = {
+            let mut g = self.0.lock().unwrap();
+            if g.cancelled {
+                return;
+            }
+            let (tx, rx) = oneshot::channel();
+            g.waiters.push(tx);
+            rx
+        };
+        let _ = rx.await;
+    }
+}
+
+impl std::fmt::Debug for CancelHandle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "CancelHandle({})", self.is_cancelled())
+    }
+}
 
 #[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
 pub struct Context {
@@ -71,6 +154,15 @@ pub struct Context {
     pub user_cookie: Option<UserCookie>,
     pub connection_id: Option<ConnectionId>,
     pub attributes: HashMap<String, String>,
+    // never crosses the wire, each side has its own.
+    #[serde(skip)]
+    cancel: Option<CancelHandle>,
+    // set by the adapter from timeout_millisecond, when the request is received.
+    #[serde(skip)]
+    deadline: Option<Instant>,
+    // set by the adapter, the format negotiated on the connection of the request.
+    #[serde(skip)]
+    format: Option<Format>,
 }
 
 impl Context {
@@ -80,6 +172,53 @@ impl Context {
             user_cookie: None,
             connection_id: None,
             attributes: HashMap::new(),
+            cancel: None,
+            deadline: None,
+            format: None,
+        }
+    }
+    pub fn set_cancel_handle(&mut self, cancel: CancelHandle) {
+        self.cancel.replace(cancel);
+    }
+    pub fn cancel_handle(&self) -> Option<&CancelHandle> {
+        self.cancel.as_ref()
+    }
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel.as_ref().map_or(false, |c| c.is_cancelled())
+    }
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+    /// the budget left before the deadline, `None` if there is no deadline.
+    pub fn remaining(&self) -> Option<Duration> {
+        self.deadline
+            .map(|d| d.saturating_duration_since(Instant::now()))
+    }
+    /// the format a hand-written servant may use for its arguments, the same as
+    /// the one of the records on the connection.
+    pub fn format(&self) -> Format {
+        self.format.unwrap_or_default()
+    }
+    /// a context for a nested call, whose timeout is the remaining budget of this one.
+    pub fn nested(&self) -> Self {
+        let mut ctx = self.clone();
+        ctx.connection_id = None;
+        ctx.deadline = None;
+        ctx.format = None;
+        if let Some(remaining) = self.remaining() {
+            ctx.timeout_millisecond = Some(remaining.as_millis() as u64);
+        }
+        ctx
+    }
+}
+
+cfg_server! {
+    impl Context {
+        pub(crate) fn set_deadline(&mut self, deadline: Instant) {
+            self.deadline.replace(deadline);
+        }
+        pub(crate) fn set_format(&mut self, format: Format) {
+            self.format.replace(format);
         }
     }
 }
@@ -88,14 +227,22 @@ impl Context {
 
 cfg_server! {
     use crate::{
-        freeze::{Freeze, MemoryDb},
+        db::SqliteDb,
+        freeze::{Freeze, MemoryDb, Storage},
         utilities::{List, Pointer},
         sync::{Arc, Mutex},
     };
+    use log::warn;
+    use std::collections::HashSet;
 
     pub(crate) type ServantEntity = Arc<Mutex<Box<dyn Servant + Send>>>;
     pub(crate) type ReportServantEntity = Arc<Mutex<Box<dyn ReportServant + Send>>>;
     pub(crate) type WatchServantEntity = Arc<Mutex<Box<dyn WatchServant + Send>>>;
+    pub(crate) type AsyncServantEntity = Arc<Mutex<Box<dyn AsyncServant + Send>>>;
+    pub(crate) type AsyncReportServantEntity = Arc<Mutex<Box<dyn AsyncReportServant + Send>>>;
+    pub(crate) type AsyncWatchServantEntity = Arc<Mutex<Box<dyn AsyncWatchServant + Send>>>;
+    pub(crate) type StreamServantEntity = Arc<Mutex<Box<dyn StreamServant + Send>>>;
+    pub(crate) type UploadServantEntity = Arc<Mutex<Box<dyn UploadServant + Send>>>;
 
     #[derive(Clone)]
     struct ServantRecord {
@@ -133,20 +280,51 @@ cfg_server! {
         servants: HashMap<Oid, ServantRecord>,
         report_servants: HashMap<Oid, ReportServantEntity>,
         watch: Option<WatchServantEntity>,
+        async_servants: HashMap<Oid, AsyncServantEntity>,
+        async_report_servants: HashMap<Oid, AsyncReportServantEntity>,
+        async_watch: Option<AsyncWatchServantEntity>,
+        stream_servants: HashMap<Oid, StreamServantEntity>,
+        upload_servants: HashMap<Oid, UploadServantEntity>,
         evictor: EvictorList,
+        // evicted but not stored, they stay in memory and are flushed with the evictor list.
+        dirty: HashSet<Oid>,
         freeze: Freeze,
     }
 
+    impl _ServantRegister {
+        // an oid names one servant, whichever kind it is. the report servants are
+        // apart, they serve the reports only.
+        fn contains(&self, oid: &Oid) -> bool {
+            self.servants.contains_key(oid)
+                || self.async_servants.contains_key(oid)
+                || self.stream_servants.contains_key(oid)
+                || self.upload_servants.contains_key(oid)
+        }
+    }
+
     #[derive(Clone)]
     pub struct ServantRegister(Arc<Mutex<_ServantRegister>>);
     impl ServantRegister {
         pub fn new(max_count_of_evictor_list: usize) -> Self {
+            Self::with_storage(max_count_of_evictor_list, Box::new(MemoryDb::new()))
+        }
+        pub fn with_sqlite(max_count_of_evictor_list: usize, file_name: &str) -> ServantResult<Self> {
+            let db = SqliteDb::new(file_name)?;
+            Ok(Self::with_storage(max_count_of_evictor_list, Box::new(db)))
+        }
+        pub fn with_storage(max_count_of_evictor_list: usize, db: Box<dyn Storage + Send>) -> Self {
             Self(Arc::new(Mutex::new(_ServantRegister {
                 servants: HashMap::new(),
                 report_servants: HashMap::new(),
                 watch: None,
+                async_servants: HashMap::new(),
+                async_report_servants: HashMap::new(),
+                async_watch: None,
+                stream_servants: HashMap::new(),
+                upload_servants: HashMap::new(),
                 evictor: EvictorList::new(max_count_of_evictor_list),
-                freeze: Freeze::new(Box::new(MemoryDb::new())),
+                dirty: HashSet::new(),
+                freeze: Freeze::new(db),
             })))
         }
         pub(crate) async fn evictor_to_vec(&self) -> Vec<Oid> {
@@ -155,15 +333,50 @@ cfg_server! {
         }
         pub async fn set_watch_servant(&self, watch: WatchServantEntity) -> Option<WatchServantEntity> {
             let mut g = self.0.lock().await;
+            g.async_watch.take();
             g.watch.replace(watch)
         }
+        pub async fn set_async_watch_servant(
+            &self,
+            watch: AsyncWatchServantEntity,
+        ) -> Option<AsyncWatchServantEntity> {
+            let mut g = self.0.lock().await;
+            g.watch.take();
+            g.async_watch.replace(watch)
+        }
         pub(crate) async fn watch_servant(&self) -> Option<WatchServantEntity> {
             let g = self.0.lock().await;
             g.watch.as_ref().map(Clone::clone)
         }
+        pub(crate) async fn async_watch_servant(&self) -> Option<AsyncWatchServantEntity> {
+            let g = self.0.lock().await;
+            g.async_watch.as_ref().map(Clone::clone)
+        }
+        pub(crate) async fn has_watch_servant(&self) -> bool {
+            let g = self.0.lock().await;
+            g.watch.is_some() || g.async_watch.is_some()
+        }
         pub(crate) async fn servants(&self) -> Vec<Oid> {
             let g = self.0.lock().await;
-            g.servants.keys().map(|v| v.clone()).collect()
+            g.servants
+                .keys()
+                .chain(g.async_servants.keys())
+                .chain(g.stream_servants.keys())
+                .chain(g.upload_servants.keys())
+                .map(|v| v.clone())
+                .collect()
+        }
+        pub(crate) async fn find_async_servant(&self, oid: &Oid) -> Option<AsyncServantEntity> {
+            let g = self.0.lock().await;
+            g.async_servants.get(&oid).map(|s| s.clone())
+        }
+        pub(crate) async fn find_stream_servant(&self, oid: &Oid) -> Option<StreamServantEntity> {
+            let g = self.0.lock().await;
+            g.stream_servants.get(&oid).map(|s| s.clone())
+        }
+        pub(crate) async fn find_upload_servant(&self, oid: &Oid) -> Option<UploadServantEntity> {
+            let g = self.0.lock().await;
+            g.upload_servants.get(&oid).map(|s| s.clone())
         }
         pub(crate) async fn find_servant(&self, oid: &Oid) -> Option<ServantEntity> {
             if let Some((servant, record)) = {
@@ -172,6 +385,13 @@ cfg_server! {
                     r.node.and_then(|v| Some(g.evictor.top(&v)));
                     return Some(r.servant);
                 }
+                // the other servants are never in the freeze, don't look them up in the storage.
+                if g.async_servants.contains_key(oid)
+                    || g.stream_servants.contains_key(oid)
+                    || g.upload_servants.contains_key(oid)
+                {
+                    return None;
+                }
                 g.freeze.load(oid).map(|s| {
                     let (node, abandoner_oid) = g.evictor.push(&oid);
                     g.servants.insert(
@@ -186,22 +406,73 @@ cfg_server! {
             }
             {
                 if let Some((oid, r)) = record {
-                    let v: Vec<u8> = r.servant.lock().await.dump().unwrap();
-                    self.0.lock().await.freeze.store(&oid, &v).unwrap();
+                    let dump = r.servant.lock().await.dump();
+                    let stored = match dump {
+                        Ok(v) => self.0.lock().await.freeze.store(&oid, &v),
+                        Err(e) => Err(e),
+                    };
+                    // the evicted servant stays in memory rather than losing its state,
+                    // it's dirty till a flush stores it.
+                    if let Err(e) = stored {
+                        warn!("evict {} error({}), it's kept in memory.", oid, e.to_string());
+                        let mut g = self.0.lock().await;
+                        g.dirty.insert(oid.clone());
+                        g.servants.insert(
+                            oid,
+                            ServantRecord {
+                                servant: r.servant,
+                                node: None,
+                            },
+                        );
+                    }
                 }
                 Some(servant)
             } else {
                 None
             }
         }
+        pub async fn flush(&self) -> usize {
+            let records: Vec<(Oid, ServantEntity)> = {
+                let g = self.0.lock().await;
+                g.evictor
+                    .to_vec()
+                    .into_iter()
+                    .chain(g.dirty.iter().cloned())
+                    .filter_map(|oid| g.servants.get(&oid).map(|r| (oid, r.servant.clone())))
+                    .collect()
+            };
+            let mut count = 0;
+            for (oid, servant) in records {
+                let dump = servant.lock().await.dump();
+                match dump {
+                    Ok(v) => match self.0.lock().await.freeze.store(&oid, &v) {
+                        Ok(_) => count += 1,
+                        Err(e) => warn!("flush {} error({})", oid, e.to_string()),
+                    },
+                    Err(e) => warn!("dump {} error({})", oid, e.to_string()),
+                }
+            }
+            count
+        }
         pub(crate) async fn report_servants(&self) -> Vec<Oid> {
             let g = self.0.lock().await;
-            g.report_servants.keys().map(|v| v.clone()).collect()
+            g.report_servants
+                .keys()
+                .chain(g.async_report_servants.keys())
+                .map(|v| v.clone())
+                .collect()
         }
         pub(crate) async fn find_report_servant(&self, oid: &Oid) -> Option<ReportServantEntity> {
             let g = self.0.lock().await;
             g.report_servants.get(&oid).map(|s| s.clone())
         }
+        pub(crate) async fn find_async_report_servant(
+            &self,
+            oid: &Oid,
+        ) -> Option<AsyncReportServantEntity> {
+            let g = self.0.lock().await;
+            g.async_report_servants.get(&oid).map(|s| s.clone())
+        }
         pub async fn add_servant(&self, category: &str, entity: ServantEntity) -> ServantResult<()> {
             let (oid, serializable) = {
                 let g = entity.lock().await;
@@ -211,7 +482,7 @@ cfg_server! {
                 )
             };
             let mut g = self.0.lock().await;
-            if g.servants.get(&oid).is_some() {
+            if g.contains(&oid) {
                 Err(ServantError::DuplicateOid)?;
             }
             let node = serializable.then2(|| {
@@ -239,6 +510,74 @@ cfg_server! {
             let mut g = self.0.lock().await;
             g.report_servants.insert(oid, entity)
         }
+        pub async fn add_async_servant(
+            &self,
+            category: &str,
+            entity: AsyncServantEntity,
+        ) -> ServantResult<()> {
+            let oid = {
+                let g = entity.lock().await;
+                Oid::new(g.name(), category)
+            };
+            let mut g = self.0.lock().await;
+            if g.contains(&oid) {
+                Err(ServantError::DuplicateOid)?;
+            }
+            g.async_servants.insert(oid, entity);
+            Ok(())
+        }
+        pub async fn add_stream_servant(
+            &self,
+            category: &str,
+            entity: StreamServantEntity,
+        ) -> ServantResult<()> {
+            let oid = {
+                let g = entity.lock().await;
+                Oid::new(g.name(), category)
+            };
+            let mut g = self.0.lock().await;
+            if g.contains(&oid) {
+                Err(ServantError::DuplicateOid)?;
+            }
+            g.stream_servants.insert(oid, entity);
+            Ok(())
+        }
+        pub async fn add_upload_servant(
+            &self,
+            category: &str,
+            entity: UploadServantEntity,
+        ) -> ServantResult<()> {
+            let oid = {
+                let g = entity.lock().await;
+                Oid::new(g.name(), category)
+            };
+            let mut g = self.0.lock().await;
+            if g.contains(&oid) {
+                Err(ServantError::DuplicateOid)?;
+            }
+            g.upload_servants.insert(oid, entity);
+            Ok(())
+        }
+        pub async fn add_async_report_servant(
+            &self,
+            category: &str,
+            entity: AsyncReportServantEntity,
+        ) -> Option<AsyncReportServantEntity> {
+            let oid = {
+                let g = entity.lock().await;
+                Oid::new(g.name(), category)
+            };
+            let mut g = self.0.lock().await;
+            g.async_report_servants.insert(oid, entity)
+        }
+        pub(crate) async fn store_bytes(&self, oid: &Oid, bytes: &[u8]) -> ServantResult<()> {
+            let mut g = self.0.lock().await;
+            g.freeze.store(oid, bytes)
+        }
+        pub(crate) async fn load_bytes(&self, oid: &Oid) -> ServantResult<Vec<u8>> {
+            let mut g = self.0.lock().await;
+            g.freeze.load_bytes(oid)
+        }
         pub async fn enroll_in_freeze<F>(&self, category: &str, f: F) -> ServantResult<()>
         where
             F: Fn(&str, &[u8]) -> ServantEntity + 'static + Send,
@@ -274,6 +613,46 @@ pub trait NotifyServant {
 
 // --
 
+#[async_trait]
+pub trait AsyncServant {
+    fn name(&self) -> &str;
+    async fn serve(&mut self, ctx: Option<Context>, req: Vec<u8>) -> Vec<u8>;
+}
+
+#[async_trait]
+pub trait AsyncWatchServant {
+    async fn serve(&mut self, req: Vec<u8>) -> Vec<u8>;
+}
+
+#[async_trait]
+pub trait AsyncReportServant {
+    fn name(&self) -> &str;
+    async fn serve(&mut self, req: Vec<u8>);
+}
+
+/// serves a request with a sequence of items. the servant is unlocked once the stream
+/// is returned, and the items are sent as the terminal grants credit.
+#[async_trait]
+pub trait StreamServant {
+    fn name(&self) -> &str;
+    async fn serve(&mut self, ctx: Option<Context>, req: Vec<u8>) -> BoxStream<'static, Vec<u8>>;
+}
+
+/// serves a request whose payload is uploaded in chunks, with one response at the end.
+/// the chunks stream ends when the terminal finishes the upload.
+#[async_trait]
+pub trait UploadServant {
+    fn name(&self) -> &str;
+    async fn serve(
+        &mut self,
+        ctx: Option<Context>,
+        req: Vec<u8>,
+        chunks: BoxStream<'static, Vec<u8>>,
+    ) -> Vec<u8>;
+}
+
+// --
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Record {
     Notice {
@@ -296,6 +675,107 @@ pub enum Record {
         oid: Option<Oid>,
         ret: Vec<u8>,
     },
+    Shutdown {
+        deadline_millisecond: u64,
+    },
+    // the handshake records must keep their positions in the enum,
+    // so that peers of any protocol version can decode them.
+    Hello {
+        protocol: u32,
+        version: String,
+        serializers: Vec<String>,
+        compressions: Vec<String>,
+        auth: Option<Vec<u8>>,
+    },
+    Welcome {
+        protocol: u32,
+        version: String,
+        serializer: String,
+        compression: String,
+    },
+    Reject {
+        reason: String,
+    },
+    Ping {
+        id: usize,
+    },
+    Pong {
+        id: usize,
+    },
+    Cancel {
+        id: usize,
+    },
+    // a stream is served within the credit granted by the terminal,
+    // so the adapter never buffers more items than the terminal asked for.
+    StreamRequest {
+        id: usize,
+        ctx: Option<Context>,
+        oid: Oid,
+        req: Vec<u8>,
+        credit: usize,
+    },
+    StreamItem {
+        id: usize,
+        item: Vec<u8>,
+    },
+    StreamEnd {
+        id: usize,
+        ret: Vec<u8>,
+    },
+    // also granted by the adapter to the terminal, for the chunks of an upload.
+    StreamCredit {
+        id: usize,
+        credit: usize,
+    },
+    UploadStart {
+        id: usize,
+        ctx: Option<Context>,
+        oid: Oid,
+        req: Vec<u8>,
+    },
+    UploadChunk {
+        id: usize,
+        chunk: Vec<u8>,
+    },
+    UploadEnd {
+        id: usize,
+    },
+    // notices on a topic go only to the terminals subscribed to it.
+    Subscribe {
+        topic: String,
+    },
+    Unsubscribe {
+        topic: String,
+    },
+    TopicNotice {
+        id: usize,
+        topic: String,
+        msg: Vec<u8>,
+    },
+    // a terminal with an identity gets the notices sent while it was away,
+    // those after the last one it acknowledged.
+    Resume {
+        identity: String,
+        last_id: usize,
+    },
+    NoticeAck {
+        id: usize,
+    },
+    // the adapter acknowledges it with the result, the terminal sends it again
+    // till then. the nonce is random for each terminal, the adapter serves a report
+    // once by it and the id.
+    ReliableReport {
+        nonce: u64,
+        id: usize,
+        oid: Oid,
+        msg: Vec<u8>,
+    },
+    // retry if the adapter couldn't serve it for now.
+    ReportAck {
+        id: usize,
+        ret: Vec<u8>,
+        retry: bool,
+    },
 }
 
 impl Default for Record {
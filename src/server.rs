@@ -4,18 +4,18 @@ use {
     crate::{
         adapter::{Adapter, AdapterRegister},
         admin::{AdminEntity, AdminServant},
-        config,
+        config::{self, StorageData},
+        db::{FileDb, SqliteDb},
+        freeze::{MemoryDb, Storage},
         help::{HelpEntity, HelpServant},
-        servant::{ServantRegister, ServantResult},
+        servant::{ConnectionId, ServantRegister, ServantResult},
         sync::{Arc, Mutex},
+        transport::{self, Connection, Listener},
     },
-    async_std::{
-        net::{TcpListener, TcpStream, ToSocketAddrs},
-        prelude::*,
-        task,
-    },
+    async_std::{prelude::*, stream, task},
     futures::{channel::mpsc::unbounded, pin_mut, select, FutureExt as _},
     log::{info, warn},
+    std::{pin::Pin, time::Duration},
 };
 
 // --
@@ -28,11 +28,28 @@ pub struct Server<T> {
 }
 
 impl<T: Clone> Server<T> {
+    /// panics if the storage in the config can't be opened, see `try_new`.
     pub fn new() -> Self {
+        Self::try_new().unwrap_or_else(|e| panic!("server storage can't be opened: {}", e))
+    }
+    pub fn try_new() -> ServantResult<Self> {
+        let config = config::Server::load();
+        output!(&config);
+        let storage: Box<dyn Storage + Send> = match &config.storage {
+            StorageData::Memory => Box::new(MemoryDb::new()),
+            StorageData::Sqlite(file_name) => Box::new(SqliteDb::new(file_name)?),
+            StorageData::Directory(dir) => Box::new(FileDb::new(dir)?),
+        };
+        Ok(Self::with_config(config, storage))
+    }
+    pub fn with_storage(storage: Box<dyn Storage + Send>) -> Self {
         let config = config::Server::load();
         output!(&config);
-        let sr = ServantRegister::new(config.max_count_of_evictor_list);
-        let ar = AdapterRegister::new();
+        Self::with_config(config, storage)
+    }
+    fn with_config(config: config::Server, storage: Box<dyn Storage + Send>) -> Self {
+        let sr = ServantRegister::with_storage(config.max_count_of_evictor_list, storage);
+        let ar = AdapterRegister::new(&config, &sr);
         Self {
             config,
             sr,
@@ -83,49 +100,79 @@ impl<T: Clone> Server<T> {
     pub fn adapter_register(&self) -> AdapterRegister {
         self.ar.clone()
     }
-    pub async fn accept_on(self, addr: impl ToSocketAddrs) -> std::io::Result<()> {
+    pub async fn set_authenticator<F>(&self, f: F)
+    where
+        F: 'static + Fn(&ConnectionId, Option<&[u8]>) -> bool + Send + Sync,
+    {
+        self.ar.set_authenticator(Arc::new(f)).await
+    }
+    pub async fn shutdown(&self) {
+        self.ar.shutdown().await
+    }
+    pub async fn accept_on(&self, addr: &str) -> std::io::Result<()> {
+        let listener = transport::bind(addr, &self.config).await?;
+        self.accept_loop(listener).await
+    }
+    pub async fn accept_with<L: Listener + 'static>(&self, listener: L) -> std::io::Result<()> {
+        self.accept_loop(Box::new(listener)).await
+    }
+    async fn accept_loop(&self, mut listener: Box<dyn Listener>) -> std::io::Result<()> {
         #[derive(Debug)]
         enum SelectedValue {
             RxNone,
-            IncomingNone,
-            Incoming(TcpStream),
+            Checkpoint,
+            Incoming(Connection),
         };
         let serve_count = self.config.serve_count_by_adapter;
         let (tx, rx) = unbounded();
-        self.ar.set_accept(tx).await;
-        let listener = TcpListener::bind(addr).await?;
-        let incoming = listener.incoming();
-        pin_mut!(incoming, rx);
+        self.ar.add_accept(tx).await;
+        let mut checkpoint: Pin<Box<dyn Stream<Item = ()> + Send>> =
+            if self.config.checkpoint_interval_second > 0 {
+                Box::pin(stream::interval(Duration::from_secs(
+                    self.config.checkpoint_interval_second,
+                )))
+            } else {
+                Box::pin(futures::stream::pending())
+            };
+        pin_mut!(rx);
         loop {
             let value = select! {
-                connection_stream = incoming.next().fuse() => match connection_stream {
-                    Some(stream) => SelectedValue::Incoming(stream?),
-                    None => SelectedValue::IncomingNone,
-                },
+                conn = listener.accept().fuse() => SelectedValue::Incoming(conn?),
                 from_rx = rx.next().fuse() => match from_rx {
                     Some(_record) => unreachable!(),
                     None => SelectedValue::RxNone,
                 },
+                _tick = checkpoint.next().fuse() => SelectedValue::Checkpoint,
             };
             match value {
-                SelectedValue::Incoming(stream) => {
-                    if self.ar.count().await == self.config.max_count_of_connection {
-                        warn!(
-                            "too many connections. drop the connection from {}",
-                            stream.peer_addr()?
-                        );
+                SelectedValue::Incoming(conn) => {
+                    if self.ar.is_closing().await {
+                        info!("server is shutting down. drop the connection from {}", conn.id);
+                    } else if !self.ar.reserve(self.config.max_count_of_connection).await {
+                        warn!("too many connections. drop the connection from {}", conn.id);
                     } else {
-                        info!("Accepting from: {}", stream.peer_addr()?);
+                        info!("Accepting from: {}", conn.id);
                         let adapter = Adapter::new(self.ar.clone(), self.sr.clone(), serve_count);
-                        task::spawn(adapter.run(stream));
+                        task::spawn(adapter.run(conn));
                     }
                 }
+                SelectedValue::Checkpoint => {
+                    let sr = self.sr.clone();
+                    task::spawn(async move {
+                        let count = sr.flush().await;
+                        info!("checkpoint: {} servants are flushed to freeze.", count);
+                    });
+                }
                 _ => {
                     info!("accept loop break due to {:?}", value);
                     break;
                 }
             }
         }
+        drop(listener);
+        self.ar.wait_closed().await;
+        let count = self.sr.flush().await;
+        info!("{} servants are flushed to freeze.", count);
         Ok(())
     }
 }
@@ -2,35 +2,47 @@
 
 use {
     crate::{
-        servant::{Context, NotifyServant, Oid, Record, ServantResult, ConnectionId},
+        compress::{Compression, CompressionStats},
+        config::{LimitData, ReconnectData, ReportRetryData},
+        format::Format,
+        handshake,
+        queue::{OverflowPolicy, QueueStats, RecordQueue},
+        servant::{CancelHandle, ConnectionId, Context, NotifyServant, Oid, Record, ServantResult},
         sync::{Arc, Condvar, Mutex},
+        transport::{self, Connection, Connector, FrameSettings},
         utilities::DropGuard,
     },
     async_std::{
-        net::TcpStream,
+        future,
         prelude::*,
         stream,
         task,
     },
-    codec::RecordCodec,
     futures::{
-        channel::mpsc::{unbounded, UnboundedSender},
+        channel::{
+            mpsc::{unbounded, UnboundedReceiver, UnboundedSender},
+            oneshot,
+        },
         pin_mut, select,
         sink::SinkExt,
         FutureExt as _,
     },
-    futures_codec::{FramedRead, FramedWrite},
     log::{info, warn},
+    serde::{de::DeserializeOwned, Serialize},
     std::{
         collections::HashMap,
-        time::{Duration, SystemTime},
+        io,
+        pin::Pin,
+        sync::Weak,
+        task::Poll,
+        time::{Duration, Instant, SystemTime},
     },
 };
 
 // --
 
 type RecordId = usize;
-type Tx = UnboundedSender<Record>;
+type Tx = RecordQueue;
 #[derive(Debug)]
 struct _Token {
     m: Mutex<Option<ServantResult<Vec<u8>>>>,
@@ -45,24 +57,108 @@ struct CallbackRecord {
     start: SystemTime,
     timeout_ms: u64,
     oid: Option<Oid>,
+    cancel: Option<CancelHandle>,
     callback: Box<dyn Fn(Option<Oid>, ServantResult<Vec<u8>>) + Send>,
 }
 type CallbackMap = HashMap<RecordId, CallbackRecord>;
+type StreamTx = UnboundedSender<ServantResult<Vec<u8>>>;
+type StreamMap = HashMap<RecordId, StreamTx>;
+
+struct UploadRecord {
+    credit: UnboundedSender<usize>,
+    ret: oneshot::Sender<ServantResult<Vec<u8>>>,
+}
+type UploadMap = HashMap<RecordId, UploadRecord>;
+struct PendingReport {
+    oid: Oid,
+    msg: Vec<u8>,
+    // the times it's sent.
+    attempt: usize,
+    delay: u64,
+    due: Instant,
+    ret: oneshot::Sender<ServantResult<()>>,
+}
+// the reports waiting for ack, bounded by `max_pending`.
+type ReportQueue = HashMap<RecordId, PendingReport>;
+type StateCallback = Arc<dyn Fn(ConnectionState) + Send + Sync>;
+
+/// the changes of the connection to the server.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConnectionState {
+    Connected,
+    Disconnected,
+    /// the attempt is starting, from 1.
+    Reconnecting(usize),
+    /// the max attempts failed, the terminal connects again only when it's used.
+    GaveUp,
+}
 
 struct _Terminal {
     addr: String,
+    connector: Option<Arc<dyn Connector>>,
+    auth: Option<Vec<u8>>,
+    serializers: Vec<String>,
+    format: Format,
+    compressions: Vec<String>,
+    compression_threshold: usize,
+    settings: Option<Arc<FrameSettings>>,
+    server_version: Option<String>,
     conn_id: Option<ConnectionId>,
     req_id: RecordId,
     report_id: RecordId,
     invoke_timeout_ms: u64,
+    heartbeat_interval_ms: u64,
+    idle_timeout_ms: u64,
     sender: Option<Tx>,
+    queue_capacity: usize,
+    overflow_policy: OverflowPolicy,
+    limits: LimitData,
+    closing: bool,
     token_pool: TokenPool,
     token_map: TokenMap,
     max_count_of_callback: usize,
     callback_map: CallbackMap,
+    stream_credit: usize,
+    stream_map: StreamMap,
+    upload_map: UploadMap,
     receiver: Option<NotifyServantEntry>,
+    topic_receivers: HashMap<String, NotifyServantEntry>,
+    identity: Option<String>,
+    last_notice_id: usize,
+    acked_notice_id: usize,
+    report_retry: ReportRetryData,
+    report_queue: ReportQueue,
+    // wakes the task sending the reports again, none if it isn't running.
+    report_wake: Option<UnboundedSender<()>>,
+    // tells the reports of this terminal from those of the others, and of its former runs.
+    report_nonce: u64,
+    reconnect: ReconnectData,
+    reconnecting: bool,
+    // held while connecting, by the calls and the reconnect task alike.
+    connect_lock: Arc<Mutex<()>>,
+    // counts the connections, the sender belongs to the last one.
+    generation: usize,
+    state_callback: Option<StateCallback>,
 }
 impl _Terminal {
+    fn clean(&mut self) {
+        if let Some(tx) = self.sender.take() {
+            tx.close();
+        }
+        self.settings.take();
+        self.closing = false;
+        self.stream_map.drain().for_each(|(_, tx)| {
+            let _ = tx.unbounded_send(Err("connection is closed.".into()));
+        });
+        self.upload_map.clear();
+    }
+    fn check_size(&self, kind: &str, size: usize, max: usize) -> ServantResult<()> {
+        if size > max {
+            Err(format!("{} of {} bytes exceeds the max {} size {}.", kind, size, kind, max).into())
+        } else {
+            Ok(())
+        }
+    }
     fn timeout_value_in_context(&self, ctx: &Option<Context>) -> u64 {
         if let Some(c) = ctx.as_ref() {
             if let Some(t) = c.timeout_millisecond {
@@ -87,16 +183,46 @@ impl Terminal {
     ) -> Self {
         let mut t = _Terminal {
             addr,
+            connector: None,
+            auth: None,
+            serializers: vec![Format::default().name().to_string()],
+            format: Format::default(),
+            compressions: vec![Compression::default().name().to_string()],
+            compression_threshold: 1024,
+            settings: None,
+            server_version: None,
             conn_id: None,
             req_id: 0,
             report_id: 0,
             invoke_timeout_ms,
+            heartbeat_interval_ms: 0,
+            idle_timeout_ms: 0,
             sender: None,
+            queue_capacity: 1024,
+            overflow_policy: OverflowPolicy::Block,
+            limits: LimitData::default(),
+            closing: false,
             token_pool: TokenPool::new(),
             token_map: TokenMap::new(),
             max_count_of_callback,
             callback_map: CallbackMap::new(),
+            stream_credit: 16,
+            stream_map: StreamMap::new(),
+            upload_map: UploadMap::new(),
             receiver: None,
+            topic_receivers: HashMap::new(),
+            identity: None,
+            last_notice_id: 0,
+            acked_notice_id: 0,
+            report_retry: ReportRetryData::default(),
+            report_queue: ReportQueue::new(),
+            report_wake: None,
+            report_nonce: rand::random(),
+            reconnect: ReconnectData::default(),
+            reconnecting: false,
+            connect_lock: Arc::new(Mutex::new(())),
+            generation: 0,
+            state_callback: None,
         };
         for _ in 0..token_count_by_terminal {
             let r = _Token {
@@ -108,16 +234,176 @@ impl Terminal {
         Self(Arc::new(Mutex::new(t)))
     }
     pub async fn clean(&self) {
-        let mut g = self.0.lock().await;
-        g.sender.take();
+        self.0.lock().await.clean();
     }
     pub async fn set_receiver(&self, receiver: NotifyServantEntry) {
         let mut g = self.0.lock().await;
         g.receiver.replace(receiver);
     }
-    async fn set_tx(&self, tx: Option<Tx>) {
+    /// receives the notices on the topic, which the adapter sends only to its subscribers.
+    pub async fn subscribe(&self, topic: &str, receiver: NotifyServantEntry) -> ServantResult<()> {
+        self.0
+            .lock()
+            .await
+            .topic_receivers
+            .insert(topic.to_string(), receiver);
+        let tx = self.tx_or_reconnect().await?;
+        tx.push(Record::Subscribe {
+            topic: topic.to_string(),
+        })
+        .await
+        .map_err(|e| e.to_string().into())
+    }
+    pub async fn unsubscribe(&self, topic: &str) -> ServantResult<()> {
+        self.0.lock().await.topic_receivers.remove(topic);
+        let tx = self.tx_or_reconnect().await?;
+        tx.push(Record::Unsubscribe {
+            topic: topic.to_string(),
+        })
+        .await
+        .map_err(|e| e.to_string().into())
+    }
+    pub async fn set_connector(&self, connector: Arc<dyn Connector>) {
+        let mut g = self.0.lock().await;
+        g.connector.replace(connector);
+    }
+    pub async fn set_auth(&self, auth: Vec<u8>) {
+        let mut g = self.0.lock().await;
+        g.auth.replace(auth);
+    }
+    /// the adapter keeps the notices for the identity while it's disconnected,
+    /// and replays them when it connects again.
+    pub async fn set_identity(&self, identity: &str) {
+        let mut g = self.0.lock().await;
+        g.identity.replace(identity.to_string());
+    }
+    /// the serializers offered in the handshake, in the order of preference.
+    pub async fn set_serializers(&self, serializers: Vec<String>) {
+        let mut g = self.0.lock().await;
+        g.serializers = serializers;
+    }
+    /// the compressions offered in the handshake, in the order of preference.
+    /// payloads smaller than the threshold are sent raw.
+    pub async fn set_compressions(&self, compressions: Vec<String>, threshold: usize) {
+        let mut g = self.0.lock().await;
+        g.compressions = compressions;
+        g.compression_threshold = threshold;
+    }
+    /// the payload bytes before and after compression, `None` if it isn't connected.
+    pub async fn compression_stats(&self) -> Option<CompressionStats> {
+        let g = self.0.lock().await;
+        g.settings.as_ref().map(|s| s.compression_stats())
+    }
+    /// the format negotiated with the server.
+    pub async fn format(&self) -> Format {
+        let g = self.0.lock().await;
+        g.format
+    }
+    pub async fn set_heartbeat(&self, heartbeat_interval_ms: u64, idle_timeout_ms: u64) {
+        let mut g = self.0.lock().await;
+        g.heartbeat_interval_ms = heartbeat_interval_ms;
+        g.idle_timeout_ms = idle_timeout_ms;
+    }
+    pub async fn set_stream_credit(&self, credit: usize) {
+        let mut g = self.0.lock().await;
+        g.stream_credit = credit.max(1);
+    }
+    pub async fn set_queue(&self, capacity: usize, policy: OverflowPolicy) {
+        let mut g = self.0.lock().await;
+        g.queue_capacity = capacity;
+        g.overflow_policy = policy;
+    }
+    /// connects again in the background when the connection is lost,
+    /// otherwise only when the terminal is used next.
+    pub async fn set_reconnect(&self, reconnect: ReconnectData) {
+        let mut g = self.0.lock().await;
+        g.reconnect = reconnect;
+    }
+    pub async fn set_state_callback<F>(&self, f: F)
+    where
+        F: 'static + Fn(ConnectionState) + Send + Sync,
+    {
+        let mut g = self.0.lock().await;
+        g.state_callback.replace(Arc::new(f));
+    }
+    async fn notify_state(&self, state: ConnectionState) {
+        info!("terminal connection state: {:?}", state);
+        let callback = self.0.lock().await.state_callback.clone();
+        if let Some(f) = callback {
+            f(state);
+        }
+    }
+    async fn disconnected(&self) {
+        self.notify_state(ConnectionState::Disconnected).await;
+        let reconnect = {
+            let mut g = self.0.lock().await;
+            if !g.reconnect.enabled || g.reconnecting {
+                return;
+            }
+            g.reconnecting = true;
+            g.reconnect.clone()
+        };
+        task::spawn(Self::reconnect(Arc::downgrade(&self.0), reconnect));
+    }
+    // holds the terminal weakly, so it stops once the terminal is dropped.
+    async fn reconnect(t: Weak<Mutex<_Terminal>>, reconnect: ReconnectData) {
+        let max_delay = reconnect.max_delay_millisecond.max(1);
+        let mut delay = reconnect.initial_delay_millisecond.max(1).min(max_delay);
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            let jitter = reconnect.jitter * (rand::random::<f64>() * 2.0 - 1.0);
+            let jittered = (delay as f64 * (1.0 + jitter)).max(0.0) as u64;
+            task::sleep(Duration::from_millis(jittered)).await;
+            let t = match t.upgrade() {
+                Some(t) => Terminal(t),
+                None => return,
+            };
+            // connected by a call in the meantime.
+            if t.0.lock().await.sender.is_some() {
+                t.0.lock().await.reconnecting = false;
+                return;
+            }
+            if reconnect.max_attempts > 0 && attempt > reconnect.max_attempts {
+                t.0.lock().await.reconnecting = false;
+                t.notify_state(ConnectionState::GaveUp).await;
+                return;
+            }
+            t.notify_state(ConnectionState::Reconnecting(attempt)).await;
+            match t.connect_to().await {
+                Ok(_) => {
+                    t.0.lock().await.reconnecting = false;
+                    return;
+                }
+                Err(e) => warn!("reconnect attempt {} failed: {}", attempt, e.to_string()),
+            }
+            delay = (delay * 2).min(max_delay);
+        }
+    }
+    pub async fn set_report_retry(&self, retry: ReportRetryData) {
         let mut g = self.0.lock().await;
-        g.sender = tx;
+        g.report_retry = retry;
+    }
+    pub async fn set_limits(&self, limits: LimitData) {
+        let mut g = self.0.lock().await;
+        g.limits = limits;
+    }
+    /// the depth of the queue to the adapter, `None` if it isn't connected.
+    pub async fn queue_stats(&self) -> Option<QueueStats> {
+        let g = self.0.lock().await;
+        g.sender.as_ref().map(|tx| tx.stats())
+    }
+    pub async fn server_version(&self) -> Option<String> {
+        let g = self.0.lock().await;
+        g.server_version.clone()
+    }
+    // the generation of the connection.
+    async fn set_tx(&self, tx: Tx) -> usize {
+        let mut g = self.0.lock().await;
+        g.sender.replace(tx);
+        g.closing = false;
+        g.generation += 1;
+        g.generation
     }
     async fn set_conn_id(&self, id: ConnectionId) {
         let mut g = self.0.lock().await;
@@ -130,6 +416,9 @@ impl Terminal {
     async fn tx_or_reconnect(&self) -> ServantResult<Tx> {
         {
             let g = self.0.lock().await;
+            if g.closing {
+                return Err("server is shutting down.".into());
+            }
             if let Some(tx) = g.sender.as_ref() {
                 return Ok(tx.clone());
             }
@@ -149,6 +438,7 @@ impl Terminal {
     pub async fn report(&self, oid: Oid, msg: Vec<u8>) -> ServantResult<()> {
         let record = {
             let mut g = self.0.lock().await;
+            g.check_size("report", msg.len(), g.limits.max_report_size)?;
             g.report_id += 1;
             Record::Report {
                 id: g.report_id,
@@ -156,13 +446,141 @@ impl Terminal {
                 msg,
             }
         };
-        let mut tx = self.tx_or_reconnect().await?;
-        if let Err(e) = tx.send(record).await {
+        let tx = self.tx_or_reconnect().await?;
+        if let Err(e) = tx.push(record).await {
             Err(e.to_string().into())
         } else {
             Ok(())
         }
     }
+    /// sends the report till the adapter acknowledges it, and returns its result.
+    /// the adapter serves it once, though it may be received more than once.
+    /// it's sent again by a task of the terminal, and isn't any more once this is dropped.
+    pub async fn reliable_report(&self, oid: Oid, msg: Vec<u8>) -> ServantResult<()> {
+        let (id, ret_rx) = {
+            let mut g = self.0.lock().await;
+            g.check_size("report", msg.len(), g.limits.max_report_size)?;
+            if g.report_queue.len() >= g.report_retry.max_pending {
+                return Err("too many reports are waiting for ack.".into());
+            }
+            g.report_id += 1;
+            let id = g.report_id;
+            let (ret, ret_rx) = oneshot::channel();
+            let report = PendingReport {
+                oid,
+                msg,
+                attempt: 0,
+                delay: g.report_retry.initial_delay_millisecond.max(1),
+                due: Instant::now(),
+                ret,
+            };
+            g.report_queue.insert(id, report);
+            let wake = match g.report_wake.as_ref() {
+                Some(wake) => wake.clone(),
+                None => {
+                    let (wake, wake_rx) = unbounded();
+                    g.report_wake.replace(wake.clone());
+                    task::spawn(Self::retry_reports(Arc::downgrade(&self.0), wake_rx));
+                    wake
+                }
+            };
+            let _ = wake.unbounded_send(());
+            (id, ret_rx)
+        };
+        let _pending = DropGuard::new((self.0.clone(), id), |(t, id)| {
+            task::block_on(async move {
+                t.lock().await.report_queue.remove(&id);
+            });
+        });
+        ret_rx
+            .await
+            .unwrap_or_else(|_| Err(format!("report {} is dropped.", id).into()))
+    }
+    // sends the reports in the queue as they're due, the delay is doubled every time.
+    // it stops when the queue is empty or the terminal is dropped.
+    async fn retry_reports(t: Weak<Mutex<_Terminal>>, mut wake: UnboundedReceiver<()>) {
+        loop {
+            let due = match t.upgrade() {
+                Some(t) => match Terminal(t).send_due_reports().await {
+                    Some(due) => due,
+                    None => return,
+                },
+                None => return,
+            };
+            let sleep = task::sleep(due.saturating_duration_since(Instant::now())).fuse();
+            pin_mut!(sleep);
+            select! {
+                _ = sleep => {},
+                woken = wake.next().fuse() => {
+                    if woken.is_none() {
+                        return;
+                    }
+                },
+            }
+        }
+    }
+    // when the next report is due, `None` if the queue is empty.
+    async fn send_due_reports(&self) -> Option<Instant> {
+        let now = Instant::now();
+        let (retry, due, failed) = {
+            let mut g = self.0.lock().await;
+            let retry = g.report_retry.clone();
+            let (max_attempts, max_delay) =
+                (retry.max_attempts.max(1), retry.max_delay_millisecond.max(1));
+            let expired: Vec<RecordId> = g
+                .report_queue
+                .iter()
+                .filter(|(_, r)| r.due <= now && r.attempt >= max_attempts)
+                .map(|(id, _)| *id)
+                .collect();
+            let failed: Vec<(RecordId, PendingReport)> = expired
+                .into_iter()
+                .filter_map(|id| g.report_queue.remove(&id).map(|r| (id, r)))
+                .collect();
+            let nonce = g.report_nonce;
+            let due: Vec<Record> = g
+                .report_queue
+                .iter_mut()
+                .filter(|(_, r)| r.due <= now)
+                .map(|(id, r)| {
+                    r.attempt += 1;
+                    r.due = now + Duration::from_millis(r.delay);
+                    r.delay = (r.delay * 2).min(max_delay);
+                    Record::ReliableReport {
+                        nonce,
+                        id: *id,
+                        oid: r.oid.clone(),
+                        msg: r.msg.clone(),
+                    }
+                })
+                .collect();
+            (retry, due, failed)
+        };
+        for (id, r) in failed {
+            let attempts = retry.max_attempts;
+            let e = format!("report {} isn't acknowledged after {} attempts.", id, attempts);
+            let _ = r.ret.send(Err(e.into()));
+        }
+        if !due.is_empty() {
+            match self.tx_or_reconnect().await {
+                Ok(tx) => {
+                    for record in due {
+                        tx.push(record)
+                            .await
+                            .unwrap_or_else(|e| warn!("report: {}", e.to_string()));
+                    }
+                }
+                Err(e) => warn!("reports aren't sent: {}", e.to_string()),
+            }
+        }
+        // the wake is taken with the lock, a report queued after it starts another task.
+        let mut g = self.0.lock().await;
+        let next = g.report_queue.values().map(|r| r.due).min();
+        if next.is_none() {
+            g.report_wake.take();
+        }
+        next
+    }
     pub async fn invoke_with_callback<F>(
         &self,
         ctx: Option<Context>,
@@ -178,6 +596,7 @@ impl Terminal {
             if g.callback_map.len() >= g.max_count_of_callback {
                 return Err("callback map is full.".into());
             }
+            g.check_size("request", req.len(), g.limits.max_request_size)?;
             g.req_id += 1;
             let id = g.req_id;
             let timeout_ms = g.timeout_value_in_context(&ctx);
@@ -187,14 +606,15 @@ impl Terminal {
                     start: SystemTime::now(),
                     oid: oid.clone(),
                     timeout_ms,
+                    cancel: ctx.as_ref().and_then(|c| c.cancel_handle().cloned()),
                     callback: Box::new(f),
                 },
             );
             id
         };
         let record = Record::Request { id, ctx, oid, req };
-        let mut tx = self.tx_or_reconnect().await?;
-        if let Err(e) = tx.send(record).await {
+        let tx = self.tx_or_reconnect().await?;
+        if let Err(e) = tx.push(record).await {
             let mut g = self.0.lock().await;
             g.callback_map.remove(&id).unwrap();
             Err(e.to_string().into())
@@ -202,6 +622,30 @@ impl Terminal {
             Ok(())
         }
     }
+    /// invokes with the arguments and the return value in the negotiated format,
+    /// for the servants written by hand or in other languages. the context is always
+    /// sent, the servant gets the format by `ctx.format()`.
+    pub async fn invoke_with_format<A, R>(
+        &self,
+        ctx: Option<Context>,
+        oid: Option<Oid>,
+        args: &A,
+    ) -> ServantResult<R>
+    where
+        A: Serialize,
+        R: DeserializeOwned,
+    {
+        // the format is known after the handshake.
+        self.tx_or_reconnect().await?;
+        let format = self.format().await;
+        let req = format.serialize(args)?;
+        let ret = self
+            .invoke(Some(ctx.unwrap_or_else(Context::new)), oid, req)
+            .await?;
+        Ok(format.deserialize(&ret)?)
+    }
+    /// the arguments are sent as they are, the proxies generated by the macros give
+    /// them in bincode whatever the format of the connection.
     pub async fn invoke(
         &self,
         ctx: Option<Context>,
@@ -210,6 +654,7 @@ impl Terminal {
     ) -> ServantResult<Vec<u8>> {
         let (index, token, timeout_ms) = {
             let mut g = self.0.lock().await;
+            g.check_size("request", req.len(), g.limits.max_request_size)?;
             if let Some(tok) = g.token_pool.pop() {
                 g.req_id += 1;
                 let id = g.req_id;
@@ -219,6 +664,7 @@ impl Terminal {
                 return Err("token pool is empty.".into());
             }
         };
+        let cancel = ctx.as_ref().and_then(|c| c.cancel_handle().cloned());
         let record = Record::Request {
             id: index,
             ctx,
@@ -227,46 +673,220 @@ impl Terminal {
         };
         let ret = match self.tx_or_reconnect().await {
             Err(e) => Err(e),
-            Ok(mut tx) => if let Err(e) = tx.send(record).await {
+            Ok(tx) => if let Err(e) = tx.push(record).await {
                 Err(e.to_string().into())
             } else {
                 let g = token.m.lock().await;
-                let mut r = token
+                let wait = token
                     .cv
                     .wait_timeout(g, Duration::from_millis(timeout_ms))
-                    .await;
-                if r.1.timed_out() {
-                    Err("timed_out.".into())
-                } else {
-                    r.0.take().unwrap()
+                    .fuse();
+                let cancelled = async {
+                    match cancel.as_ref() {
+                        Some(c) => c.cancelled().await,
+                        None => future::pending::<()>().await,
+                    }
+                }
+                .fuse();
+                pin_mut!(wait, cancelled);
+                let r = select! {
+                    r = wait => {
+                        let mut r = r;
+                        if r.1.timed_out() {
+                            None
+                        } else {
+                            r.0.take()
+                        }
+                    },
+                    _ = cancelled => None,
+                };
+                match r {
+                    Some(ret) => ret,
+                    None => {
+                        // the adapter drops the request, and frees its serve count.
+                        if let Err(e) = tx.force(Record::Cancel { id: index }) {
+                            warn!("{}", e.to_string());
+                        }
+                        if cancel.as_ref().map_or(false, |c| c.is_cancelled()) {
+                            Err("cancelled.".into())
+                        } else {
+                            Err("timed_out.".into())
+                        }
+                    }
                 }
             },
         };
         {
             let mut g = self.0.lock().await;
             g.token_map.remove(&index);
-            g.token_pool.push(token);
         }
+        // a late response may have filled the token after the timeout.
+        token.m.lock().await.take();
+        self.0.lock().await.token_pool.push(token);
         ret
     }
+    pub async fn invoke_stream(
+        &self,
+        ctx: Option<Context>,
+        oid: Oid,
+        req: Vec<u8>,
+    ) -> ServantResult<ResponseStream> {
+        let (id, credit, rx) = {
+            let mut g = self.0.lock().await;
+            g.check_size("request", req.len(), g.limits.max_request_size)?;
+            g.req_id += 1;
+            let id = g.req_id;
+            let (tx, rx) = unbounded();
+            g.stream_map.insert(id, tx);
+            (id, g.stream_credit, rx)
+        };
+        let record = Record::StreamRequest {
+            id,
+            ctx,
+            oid,
+            req,
+            credit,
+        };
+        let sent = match self.tx_or_reconnect().await {
+            Ok(tx) => match tx.push(record).await {
+                Ok(_) => Ok(tx),
+                Err(e) => Err(e.to_string().into()),
+            },
+            Err(e) => Err(e),
+        };
+        match sent {
+            Ok(tx) => Ok(ResponseStream {
+                id,
+                terminal: self.clone(),
+                tx,
+                rx,
+                window: credit,
+                consumed: 0,
+                done: false,
+            }),
+            Err(e) => {
+                self.0.lock().await.stream_map.remove(&id);
+                Err(e)
+            }
+        }
+    }
+    pub async fn upload<S>(
+        &self,
+        ctx: Option<Context>,
+        oid: Oid,
+        req: Vec<u8>,
+        chunks: S,
+    ) -> ServantResult<Vec<u8>>
+    where
+        S: Stream<Item = Vec<u8>> + Send,
+    {
+        let (id, timeout_ms, credit_rx, ret_rx) = {
+            let mut g = self.0.lock().await;
+            g.check_size("request", req.len(), g.limits.max_request_size)?;
+            g.req_id += 1;
+            let id = g.req_id;
+            let (credit, credit_rx) = unbounded();
+            let (ret, ret_rx) = oneshot::channel();
+            g.upload_map.insert(id, UploadRecord { credit, ret });
+            (id, g.timeout_value_in_context(&ctx), credit_rx, ret_rx)
+        };
+        let tx = match self.tx_or_reconnect().await {
+            Ok(tx) => tx,
+            Err(e) => {
+                self.0.lock().await.upload_map.remove(&id);
+                return Err(e);
+            }
+        };
+        // the adapter drops the upload, if it isn't finished.
+        let mut finished = DropGuard::new((false, tx.clone(), id), |(finished, tx, id)| {
+            if !finished {
+                let _ = tx.force(Record::Cancel { id });
+            }
+        });
+        let record = Record::UploadStart { id, ctx, oid, req };
+        tx.push(record).await.map_err(|e| e.to_string())?;
+
+        // the chunks are sent within the credit granted by the adapter. the credit
+        // stream ends when the response comes, which may be an early error.
+        let mut credit = 0;
+        pin_mut!(chunks, credit_rx);
+        while let Some(chunk) = chunks.next().await {
+            while credit == 0 {
+                match credit_rx.next().await {
+                    Some(c) => credit += c,
+                    None => break,
+                }
+            }
+            if credit == 0 {
+                break;
+            }
+            credit -= 1;
+            let record = Record::UploadChunk { id, chunk };
+            tx.push(record).await.map_err(|e| e.to_string())?;
+        }
+        tx.push(Record::UploadEnd { id })
+            .await
+            .map_err(|e| e.to_string())?;
+        finished.0 = true;
+
+        match future::timeout(Duration::from_millis(timeout_ms), ret_rx).await {
+            Ok(Ok(ret)) => ret,
+            Ok(Err(_)) => Err("connection is closed.".into()),
+            Err(_) => {
+                finished.0 = false;
+                self.0.lock().await.upload_map.remove(&id);
+                Err("timed_out.".into())
+            }
+        }
+    }
     async fn received(&self, record: Record) {
         match record {
             Record::Notice { id, msg } => {
-                let _id = id;
                 let mut g = self.0.lock().await;
+                if g.identity.is_some() {
+                    // replayed after a reconnection, it's received already.
+                    if id <= g.last_notice_id {
+                        return;
+                    }
+                    g.last_notice_id = id;
+                }
+                if let Err(e) = g.check_size("notice", msg.len(), g.limits.max_notice_size) {
+                    warn!("notice {} from {} is dropped: {}", id, g.addr, e);
+                    return;
+                }
                 if let Some(receiver) = g.receiver.as_mut() {
                     receiver.serve(msg);
                 }
             }
+            Record::TopicNotice { id, topic, msg } => {
+                let mut g = self.0.lock().await;
+                if let Err(e) = g.check_size("notice", msg.len(), g.limits.max_notice_size) {
+                    warn!("notice {} on {} from {} is dropped: {}", id, topic, g.addr, e);
+                    return;
+                }
+                match g.topic_receivers.get_mut(&topic) {
+                    Some(receiver) => receiver.serve(msg),
+                    None => warn!("received notice {} on {}, which isn't subscribed.", id, topic),
+                }
+            }
             Record::Response { id, oid, ret } => {
                 let _oid = oid;
-                let (token, callback) = {
+                let (token, callback, upload, checked, format) = {
                     let mut g = self.0.lock().await;
-                    (g.token_map.remove(&id), g.callback_map.remove(&id))
+                    (
+                        g.token_map.remove(&id),
+                        g.callback_map.remove(&id),
+                        g.upload_map.remove(&id),
+                        g.check_size("response", ret.len(), g.limits.max_response_size),
+                        g.format,
+                    )
                 };
-                let ret = match bincode::deserialize(&ret) {
-                    Ok(ret) => ret,
-                    Err(e) => Err(e.to_string().into()),
+                let ret = match checked {
+                    Ok(()) => match format.deserialize(&ret) {
+                        Ok(ret) => ret,
+                        Err(e) => Err(e.to_string().into()),
+                    },
+                    Err(e) => Err(e),
                 };
                 if let Some(token) = token {
                     let mut g = token.m.lock().await;
@@ -274,6 +894,8 @@ impl Terminal {
                     token.cv.notify_one();
                 } else if let Some(r) = callback {
                     (r.callback)(r.oid, ret);
+                } else if let Some(r) = upload {
+                    let _ = r.ret.send(ret);
                 } else {
                     warn!(
                         "received {:?}, but can't find id: {} in token map and callback map.",
@@ -281,22 +903,190 @@ impl Terminal {
                     );
                 }
             }
-            Record::Report { .. } => unreachable!(),
-            Record::Request { .. } => unreachable!(),
+            Record::StreamCredit { id, credit } => {
+                let g = self.0.lock().await;
+                if let Some(r) = g.upload_map.get(&id) {
+                    let _ = r.credit.unbounded_send(credit);
+                }
+            }
+            Record::StreamItem { id, item } => {
+                let mut g = self.0.lock().await;
+                if let Some(tx) = g.stream_map.get(&id) {
+                    if tx.unbounded_send(Ok(item)).is_err() {
+                        g.stream_map.remove(&id);
+                    }
+                } else {
+                    warn!("received stream item, but can't find id: {} in stream map.", id);
+                }
+            }
+            Record::ReportAck { id, ret, retry } => {
+                let format = self.0.lock().await.format;
+                let ret: ServantResult<()> = match format.deserialize(&ret) {
+                    Ok(ret) => ret,
+                    Err(e) => Err(e.to_string().into()),
+                };
+                // it's sent again when it's due.
+                if retry {
+                    info!("report {} isn't served for now: {:?}", id, ret);
+                    return;
+                }
+                let report = self.0.lock().await.report_queue.remove(&id);
+                // none if an earlier attempt is acknowledged already, or it's given up.
+                if let Some(report) = report {
+                    let _ = report.ret.send(ret);
+                }
+            }
+            Record::StreamEnd { id, ret } => {
+                let (tx, format) = {
+                    let mut g = self.0.lock().await;
+                    (g.stream_map.remove(&id), g.format)
+                };
+                let ret: ServantResult<()> = match format.deserialize(&ret) {
+                    Ok(ret) => ret,
+                    Err(e) => Err(e.to_string().into()),
+                };
+                match (tx, ret) {
+                    (Some(tx), Err(e)) => {
+                        let _ = tx.unbounded_send(Err(e));
+                    }
+                    (Some(_), Ok(())) => {}
+                    (None, ret) => warn!(
+                        "received stream end {:?}, but can't find id: {} in stream map.",
+                        ret, id
+                    ),
+                }
+            }
+            Record::Shutdown {
+                deadline_millisecond,
+            } => {
+                info!(
+                    "server is shutting down in {} milliseconds.",
+                    deadline_millisecond
+                );
+                let mut g = self.0.lock().await;
+                g.closing = true;
+            }
+            // a misbehaving server, the records are only sent to adapters.
+            _ => {
+                let g = self.0.lock().await;
+                warn!("record from {} is only sent to adapters, it's dropped.", g.addr);
+            }
         }
     }
-    pub async fn connect_to(&self) -> std::io::Result<()> {
-        let stream = {
+    pub async fn connect_to(&self) -> io::Result<()> {
+        let lock = self.0.lock().await.connect_lock.clone();
+        let _connecting = lock.lock().await;
+        // connected by another one while this waited.
+        if self.0.lock().await.sender.is_some() {
+            return Ok(());
+        }
+        let connector = {
+            let mut g = self.0.lock().await;
+            match g.connector.as_ref() {
+                Some(c) => c.clone(),
+                None => {
+                    let c = transport::connector(&g.addr, None)?;
+                    g.connector.replace(c.clone());
+                    c
+                }
+            }
+        };
+        let mut conn = connector.connect().await?;
+        conn.set_max_frame_size(self.0.lock().await.limits.max_frame_size);
+        info!("connected to {} from {}", self.0.lock().await.addr, conn.id);
+        self.handshake(&mut conn).await?;
+        let resume = {
             let g = self.0.lock().await;
-            TcpStream::connect(&g.addr).await?
+            g.identity.clone().map(|identity| Record::Resume {
+                identity,
+                last_id: g.last_notice_id,
+            })
         };
-        info!("connected to {}", stream.peer_addr()?);
+        if let Some(record) = resume {
+            conn.writer.send(record).await?;
+        }
+        // the subscriptions are kept by the connection on the server side.
+        let topics: Vec<String> = self.0.lock().await.topic_receivers.keys().cloned().collect();
+        for topic in topics {
+            conn.writer.send(Record::Subscribe { topic }).await?;
+        }
 
-        let conn = self.clone();
+        let t = self.clone();
         task::spawn(async move {
-            let r = conn.run(stream).await;
+            let r = t.run(conn).await;
             info!("terminal run result: {:?}", r);
+            if t.0.lock().await.sender.is_none() {
+                t.disconnected().await;
+            }
         });
+        self.wait_for_sender().await?;
+        self.notify_state(ConnectionState::Connected).await;
+        Ok(())
+    }
+    async fn handshake(&self, conn: &mut Connection) -> io::Result<()> {
+        let (auth, serializers, compressions, timeout_ms) = {
+            let g = self.0.lock().await;
+            (
+                g.auth.clone(),
+                g.serializers.clone(),
+                g.compressions.clone(),
+                g.invoke_timeout_ms,
+            )
+        };
+        conn.writer
+            .send(handshake::hello(auth, &serializers, &compressions))
+            .await?;
+        let reply = future::timeout(Duration::from_millis(timeout_ms), conn.reader.next())
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "handshake timed out."))?;
+        match reply {
+            Some(Ok(Record::Welcome {
+                protocol,
+                version,
+                serializer,
+                compression,
+            })) => {
+                info!(
+                    "welcome from server, protocol: {}, version: {}, serializer: {}, compression: {}",
+                    protocol, version, serializer, compression
+                );
+                let format = Format::from_name(&serializer).ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("serializer {} isn't supported.", serializer),
+                    )
+                })?;
+                let compression = Compression::from_name(&compression).ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("compression {} isn't supported.", compression),
+                    )
+                })?;
+                // the records after the welcome are in the negotiated format and compression.
+                let mut g = self.0.lock().await;
+                conn.settings.set_format(format);
+                conn.settings
+                    .set_compression(compression, g.compression_threshold);
+                g.settings.replace(conn.settings.clone());
+                g.format = format;
+                g.server_version.replace(version);
+                Ok(())
+            }
+            Some(Ok(Record::Reject { reason })) => {
+                Err(io::Error::new(io::ErrorKind::ConnectionRefused, reason))
+            }
+            Some(Ok(record)) => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unexpected record in handshake: {:?}", record),
+            )),
+            Some(Err(e)) => Err(e),
+            None => Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "connection is closed in handshake, the server may not support the protocol.",
+            )),
+        }
+    }
+    async fn wait_for_sender(&self) -> std::io::Result<()> {
         loop {
             task::sleep(Duration::from_micros(10)).await;
             if self.0.lock().await.sender.is_some() {
@@ -305,7 +1095,7 @@ impl Terminal {
         }
         Ok(())
     }
-    async fn run(&self, stream: TcpStream) -> std::io::Result<()> {
+    async fn run(&self, conn: Connection) -> std::io::Result<()> {
         #[derive(Debug)]
         enum SelectedValue {
             ReadNone,
@@ -315,26 +1105,47 @@ impl Terminal {
             Write(Record),
         }
 
-        let (tx, rx) = unbounded();
-        self.set_tx(Some(tx)).await;
-        self.set_conn_id(stream.local_addr()?).await;
-        let _terminal_clean = DropGuard::new(self.clone(), |t| {
+        let Connection {
+            id: local,
+            reader: read_framed,
+            writer: mut write_framed,
+            ..
+        } = conn;
+        let tx = {
+            let g = self.0.lock().await;
+            RecordQueue::new(g.queue_capacity, g.overflow_policy)
+        };
+        let rx = tx.receiver();
+        let generation = self.set_tx(tx).await;
+        self.set_conn_id(local).await;
+        // a later connection is left alone, it has its own sender.
+        let _terminal_clean = DropGuard::new(self.clone(), move |t| {
             task::block_on(async move {
                 info!("terminal quit.");
-                t.clean().await;
+                let mut g = t.0.lock().await;
+                if g.generation == generation {
+                    g.clean();
+                }
             });
         });
 
-        let (reader, writer) = (&stream, &stream);
-        let read_framed = FramedRead::new(reader, RecordCodec::<u32, Record>::default());
-        let mut write_framed = FramedWrite::new(writer, RecordCodec::<u32, Record>::default());
         let interval = stream::interval(Duration::from_millis(1000));
+        let (addr, heartbeat_ms, idle_ms) = {
+            let g = self.0.lock().await;
+            (g.addr.clone(), g.heartbeat_interval_ms, g.idle_timeout_ms)
+        };
+        let mut last_received = Instant::now();
+        let mut last_ping = Instant::now();
+        let mut ping_id = 0;
 
         pin_mut!(read_framed, rx, interval);
         loop {
             let value = select! {
                 from_adapter = read_framed.next().fuse() => match from_adapter {
-                    Some(record) => SelectedValue::Read(record?),
+                    Some(record) => SelectedValue::Read(record.map_err(|e| {
+                        warn!("read from {} failed: {}", &addr, e.to_string());
+                        e
+                    })?),
                     None => SelectedValue::ReadNone,
                 },
                 to_adapter = rx.next().fuse() => match to_adapter {
@@ -344,10 +1155,37 @@ impl Terminal {
                 _tick = interval.next().fuse() => SelectedValue::Tick,
             };
 
+            if let SelectedValue::Read(_) = &value {
+                last_received = Instant::now();
+            }
+
             match value {
+                SelectedValue::Read(Record::Ping { id }) => {
+                    write_framed.send(Record::Pong { id }).await?
+                }
+                SelectedValue::Read(Record::Pong { .. }) => {}
                 SelectedValue::Read(record) => self.received(record).await,
                 SelectedValue::Write(record) => write_framed.send(record).await?,
-                SelectedValue::Tick => self.tick().await,
+                SelectedValue::Tick => {
+                    self.tick().await;
+                    let idle = last_received.elapsed();
+                    if idle_ms > 0 && idle >= Duration::from_millis(idle_ms) {
+                        warn!(
+                            "no record from {} in {} milliseconds, the connection is dead.",
+                            &addr,
+                            idle.as_millis()
+                        );
+                        break;
+                    }
+                    if heartbeat_ms > 0
+                        && idle >= Duration::from_millis(heartbeat_ms)
+                        && last_ping.elapsed() >= Duration::from_millis(heartbeat_ms)
+                    {
+                        ping_id += 1;
+                        last_ping = Instant::now();
+                        write_framed.send(Record::Ping { id: ping_id }).await?;
+                    }
+                }
                 _ => {
                     info!("loop break due to SelectedValue: {:?}", value);
                     break;
@@ -359,12 +1197,14 @@ impl Terminal {
     async fn tick(&self) {
         let now = SystemTime::now();
         let mut g = self.0.lock().await;
-        let v: Vec<RecordId> = g
+        let v: Vec<(RecordId, &str)> = g
             .callback_map
             .iter()
             .map(|(id, record)| {
-                if record.start <= now - Duration::from_millis(record.timeout_ms) {
-                    Some(*id)
+                if record.cancel.as_ref().map_or(false, |c| c.is_cancelled()) {
+                    Some((*id, "cancelled in callback."))
+                } else if record.start <= now - Duration::from_millis(record.timeout_ms) {
+                    Some((*id, "timeout in callback."))
                 } else {
                     None
                 }
@@ -372,11 +1212,24 @@ impl Terminal {
             .filter(|x| x.is_some())
             .map(|x| x.unwrap())
             .collect();
-        v.iter().for_each(|x| {
-            if let Some(record) = g.callback_map.remove(&x) {
-                (record.callback)(record.oid, Err("timeout in callback.".into()));
+        v.iter().for_each(|(x, reason)| {
+            if let Some(record) = g.callback_map.remove(x) {
+                (record.callback)(record.oid, Err((*reason).into()));
+            }
+            if let Some(tx) = g.sender.as_ref() {
+                tx.force(Record::Cancel { id: *x })
+                    .unwrap_or_else(|e| warn!("{}", e.to_string()));
             }
         });
+        if g.identity.is_some() && g.last_notice_id > g.acked_notice_id {
+            let id = g.last_notice_id;
+            if let Some(tx) = g.sender.clone() {
+                match tx.force(Record::NoticeAck { id }) {
+                    Ok(_) => g.acked_notice_id = id,
+                    Err(e) => warn!("{}", e.to_string()),
+                }
+            }
+        }
     }
     pub fn proxy<T, F>(&self, name: &str, f: F) -> T
     where
@@ -391,3 +1244,56 @@ impl Terminal {
         f(ctx, name, self)
     }
 }
+
+// --
+
+/// the items of a stream call. the credit is granted back to the adapter as the items
+/// are consumed, and dropping the stream before its end cancels the call.
+pub struct ResponseStream {
+    id: RecordId,
+    terminal: Terminal,
+    tx: Tx,
+    rx: UnboundedReceiver<ServantResult<Vec<u8>>>,
+    window: usize,
+    consumed: usize,
+    done: bool,
+}
+
+impl Stream for ResponseStream {
+    type Item = ServantResult<Vec<u8>>;
+    fn poll_next(
+        self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        match Pin::new(&mut this.rx).poll_next(cx) {
+            Poll::Ready(Some(Ok(item))) => {
+                this.consumed += 1;
+                if this.consumed * 2 >= this.window {
+                    let credit = std::mem::take(&mut this.consumed);
+                    this.tx
+                        .force(Record::StreamCredit { id: this.id, credit })
+                        .unwrap_or_else(|e| warn!("{}", e.to_string()));
+                }
+                Poll::Ready(Some(Ok(item)))
+            }
+            Poll::Ready(None) => {
+                this.done = true;
+                Poll::Ready(None)
+            }
+            other => other,
+        }
+    }
+}
+
+impl Drop for ResponseStream {
+    fn drop(&mut self) {
+        if !self.done {
+            let _ = self.tx.force(Record::Cancel { id: self.id });
+            let (t, id) = (self.terminal.clone(), self.id);
+            task::spawn(async move {
+                t.0.lock().await.stream_map.remove(&id);
+            });
+        }
+    }
+}
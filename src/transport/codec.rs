@@ -0,0 +1,288 @@
+// -- codec.rs --
+
+use {
+    crate::{
+        compress::{Compression, CompressionStats},
+        format::Format,
+        servant::Record,
+    },
+    futures_codec::{BytesMut, Decoder, Encoder},
+    std::{
+        io,
+        sync::{
+            atomic::{AtomicU64, AtomicUsize, Ordering},
+            Arc, Mutex,
+        },
+    },
+};
+
+// --
+
+pub(crate) const DEFAULT_MAX_FRAME_SIZE: usize = 16 * 1024 * 1024;
+const HEADER_SIZE: usize = 4;
+// the first byte of a payload, when the connection is compressed.
+const FLAG_RAW: u8 = 0;
+const FLAG_COMPRESSED: u8 = 1;
+
+/// the settings of a connection, which the adapter or terminal may change
+/// after it's established, such as the limit from its config and the format
+/// and compression negotiated in the handshake.
+#[derive(Debug)]
+pub(crate) struct FrameSettings {
+    max_frame_size: AtomicUsize,
+    format: Mutex<Format>,
+    compression: Mutex<Compression>,
+    compression_threshold: AtomicUsize,
+    raw_sent: AtomicU64,
+    wire_sent: AtomicU64,
+    raw_received: AtomicU64,
+    wire_received: AtomicU64,
+}
+impl Default for FrameSettings {
+    fn default() -> Self {
+        Self {
+            max_frame_size: AtomicUsize::new(DEFAULT_MAX_FRAME_SIZE),
+            format: Mutex::new(Format::default()),
+            compression: Mutex::new(Compression::default()),
+            compression_threshold: AtomicUsize::new(0),
+            raw_sent: AtomicU64::new(0),
+            wire_sent: AtomicU64::new(0),
+            raw_received: AtomicU64::new(0),
+            wire_received: AtomicU64::new(0),
+        }
+    }
+}
+impl FrameSettings {
+    pub(crate) fn max_frame_size(&self) -> usize {
+        self.max_frame_size.load(Ordering::Relaxed)
+    }
+    pub(crate) fn set_max_frame_size(&self, size: usize) {
+        self.max_frame_size.store(size, Ordering::Relaxed);
+    }
+    pub(crate) fn format(&self) -> Format {
+        *self.format.lock().unwrap()
+    }
+    pub(crate) fn set_format(&self, format: Format) {
+        *self.format.lock().unwrap() = format;
+    }
+    pub(crate) fn compression(&self) -> Compression {
+        *self.compression.lock().unwrap()
+    }
+    /// payloads smaller than the threshold are sent raw.
+    pub(crate) fn set_compression(&self, compression: Compression, threshold: usize) {
+        *self.compression.lock().unwrap() = compression;
+        self.compression_threshold.store(threshold, Ordering::Relaxed);
+    }
+    pub(crate) fn compression_stats(&self) -> CompressionStats {
+        CompressionStats {
+            compression: self.compression().name().to_string(),
+            raw_sent: self.raw_sent.load(Ordering::Relaxed),
+            wire_sent: self.wire_sent.load(Ordering::Relaxed),
+            raw_received: self.raw_received.load(Ordering::Relaxed),
+            wire_received: self.wire_received.load(Ordering::Relaxed),
+        }
+    }
+    pub(crate) fn encode(&self, mut record: Record) -> io::Result<Vec<u8>> {
+        let compression = self.compression();
+        if compression != Compression::None {
+            if let Some(payload) = payload_mut(&mut record) {
+                *payload = self.pack(compression, std::mem::take(payload))?;
+            }
+        }
+        let data = self
+            .format()
+            .serialize(&record)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        self.check(data.len())?;
+        Ok(data)
+    }
+    pub(crate) fn decode(&self, data: &[u8]) -> io::Result<Record> {
+        self.check(data.len())?;
+        let mut record = self
+            .format()
+            .deserialize(data)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let compression = self.compression();
+        if compression != Compression::None {
+            if let Some(payload) = payload_mut(&mut record) {
+                *payload = self.unpack(compression, std::mem::take(payload))?;
+            }
+        }
+        Ok(record)
+    }
+    // the payload is compressed only if it's large enough, and gets smaller.
+    fn pack(&self, compression: Compression, mut payload: Vec<u8>) -> io::Result<Vec<u8>> {
+        let raw = payload.len() as u64;
+        if payload.len() >= self.compression_threshold.load(Ordering::Relaxed) {
+            let data = compression.compress(&payload)?;
+            if data.len() < payload.len() {
+                payload = Vec::with_capacity(data.len() + 1);
+                payload.push(FLAG_COMPRESSED);
+                payload.extend_from_slice(&data);
+            } else {
+                payload.insert(0, FLAG_RAW);
+            }
+        } else {
+            payload.insert(0, FLAG_RAW);
+        }
+        self.raw_sent.fetch_add(raw, Ordering::Relaxed);
+        self.wire_sent.fetch_add(payload.len() as u64, Ordering::Relaxed);
+        Ok(payload)
+    }
+    fn unpack(&self, compression: Compression, mut payload: Vec<u8>) -> io::Result<Vec<u8>> {
+        let wire = payload.len() as u64;
+        let payload = match payload.first() {
+            Some(&FLAG_RAW) => {
+                payload.remove(0);
+                payload
+            }
+            Some(&FLAG_COMPRESSED) => compression.decompress(&payload[1..], self.max_frame_size())?,
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "payload of a compressed connection has no flag.",
+                ))
+            }
+        };
+        self.raw_received.fetch_add(payload.len() as u64, Ordering::Relaxed);
+        self.wire_received.fetch_add(wire, Ordering::Relaxed);
+        Ok(payload)
+    }
+    pub(crate) fn check(&self, size: usize) -> io::Result<()> {
+        let max = self.max_frame_size();
+        if size > max {
+            Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("frame of {} bytes exceeds the max frame size {}.", size, max),
+            ))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// a record in the format of the connection, after its length as a big-endian u32.
+/// the length is checked before the frame is buffered, a peer can't make us allocate
+/// more than the limit.
+pub(crate) struct FrameCodec(Arc<FrameSettings>);
+impl FrameCodec {
+    pub(crate) fn new(settings: Arc<FrameSettings>) -> Self {
+        Self(settings)
+    }
+}
+
+impl Encoder for FrameCodec {
+    type Item = Record;
+    type Error = io::Error;
+    fn encode(&mut self, item: Record, dst: &mut BytesMut) -> io::Result<()> {
+        let data = self.0.encode(item)?;
+        dst.reserve(HEADER_SIZE + data.len());
+        dst.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        dst.extend_from_slice(&data);
+        Ok(())
+    }
+}
+
+impl Decoder for FrameCodec {
+    type Item = Record;
+    type Error = io::Error;
+    fn decode(&mut self, src: &mut BytesMut) -> io::Result<Option<Record>> {
+        if src.len() < HEADER_SIZE {
+            return Ok(None);
+        }
+        let mut header = [0u8; HEADER_SIZE];
+        header.copy_from_slice(&src[..HEADER_SIZE]);
+        let size = u32::from_be_bytes(header) as usize;
+        self.0.check(size)?;
+        if src.len() < HEADER_SIZE + size {
+            src.reserve(HEADER_SIZE + size - src.len());
+            return Ok(None);
+        }
+        let _header = src.split_to(HEADER_SIZE);
+        let data = src.split_to(size);
+        self.0.decode(&data).map(Some)
+    }
+}
+
+// the bytes of a servant, which are compressed on a compressed connection.
+fn payload_mut(record: &mut Record) -> Option<&mut Vec<u8>> {
+    match record {
+        Record::Notice { msg, .. } => Some(msg),
+        Record::Report { msg, .. } => Some(msg),
+        Record::TopicNotice { msg, .. } => Some(msg),
+        Record::Request { req, .. } => Some(req),
+        Record::Response { ret, .. } => Some(ret),
+        Record::StreamRequest { req, .. } => Some(req),
+        Record::StreamItem { item, .. } => Some(item),
+        Record::StreamEnd { ret, .. } => Some(ret),
+        Record::UploadStart { req, .. } => Some(req),
+        Record::UploadChunk { chunk, .. } => Some(chunk),
+        Record::ReliableReport { msg, .. } => Some(msg),
+        Record::ReportAck { ret, .. } => Some(ret),
+        Record::Shutdown { .. } => None,
+        Record::Hello { .. } => None,
+        Record::Welcome { .. } => None,
+        Record::Reject { .. } => None,
+        Record::Ping { .. } => None,
+        Record::Pong { .. } => None,
+        Record::Cancel { .. } => None,
+        Record::StreamCredit { .. } => None,
+        Record::UploadEnd { .. } => None,
+        Record::Subscribe { .. } => None,
+        Record::Unsubscribe { .. } => None,
+        Record::Resume { .. } => None,
+        Record::NoticeAck { .. } => None,
+    }
+}
+
+// --
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frame_codec_test() {
+        let settings = Arc::new(FrameSettings::default());
+        let mut codec = FrameCodec::new(settings.clone());
+        let mut buf = BytesMut::new();
+        codec.encode(Record::Ping { id: 7 }, &mut buf).unwrap();
+        let mut half = buf.split_to(buf.len() - 1);
+        assert!(codec.decode(&mut half).unwrap().is_none());
+        half.unsplit(buf);
+        assert!(matches!(codec.decode(&mut half).unwrap(), Some(Record::Ping { id: 7 })));
+
+        settings.set_format(Format::Json);
+        let mut buf = BytesMut::new();
+        codec.encode(Record::Pong { id: 8 }, &mut buf).unwrap();
+        assert_eq!(&buf[HEADER_SIZE..], br#"{"Pong":{"id":8}}"#);
+        assert!(matches!(codec.decode(&mut buf).unwrap(), Some(Record::Pong { id: 8 })));
+
+        settings.set_max_frame_size(16);
+        let mut buf = BytesMut::new();
+        buf.extend_from_slice(&u32::MAX.to_be_bytes());
+        assert!(codec.decode(&mut buf).is_err());
+        let notice = Record::Notice { id: 1, msg: vec![0; 32] };
+        assert!(codec.encode(notice, &mut BytesMut::new()).is_err());
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn compressed_payload_test() {
+        let settings = Arc::new(FrameSettings::default());
+        settings.set_compression(Compression::Zstd, 64);
+        let mut codec = FrameCodec::new(settings.clone());
+        let mut buf = BytesMut::new();
+        codec.encode(Record::Notice { id: 1, msg: vec![0; 4096] }, &mut buf).unwrap();
+        codec.encode(Record::Notice { id: 2, msg: vec![0; 8] }, &mut buf).unwrap();
+        for (id, size) in [(1, 4096), (2, 8)].iter() {
+            match codec.decode(&mut buf).unwrap() {
+                Some(Record::Notice { id: x, msg }) => assert_eq!((x, msg.len()), (*id, *size)),
+                r => panic!("unexpected: {:?}", r),
+            }
+        }
+        let stats = settings.compression_stats();
+        assert_eq!((stats.raw_sent, stats.raw_received), (4104, 4104));
+        assert!(stats.ratio_sent() < 0.1);
+    }
+}
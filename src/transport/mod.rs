@@ -0,0 +1,167 @@
+// -- transport/mod.rs --
+
+mod codec;
+mod tcp;
+#[cfg(unix)]
+mod unix;
+cfg_tls! {
+    mod tls;
+}
+cfg_websocket! {
+    mod ws;
+}
+
+use {
+    crate::servant::{ConnectionId, Record},
+    async_trait::async_trait,
+    futures::{
+        io::{AsyncRead, AsyncReadExt as _, AsyncWrite},
+        sink::Sink,
+        stream::{BoxStream, StreamExt as _},
+    },
+    futures_codec::{FramedRead, FramedWrite},
+    std::{io, pin::Pin, sync::Arc},
+};
+
+pub(crate) use codec::FrameSettings;
+
+// --
+
+pub type RecordReader = BoxStream<'static, io::Result<Record>>;
+pub type RecordWriter = Pin<Box<dyn Sink<Record, Error = io::Error> + Send>>;
+
+pub struct Connection {
+    pub(crate) id: ConnectionId,
+    pub(crate) reader: RecordReader,
+    pub(crate) writer: RecordWriter,
+    pub(crate) settings: Arc<FrameSettings>,
+}
+
+impl Connection {
+    pub fn new(id: ConnectionId, reader: RecordReader, writer: RecordWriter) -> Self {
+        Self::with_settings(id, reader, writer, Arc::new(FrameSettings::default()))
+    }
+    pub(crate) fn with_settings(
+        id: ConnectionId,
+        reader: RecordReader,
+        writer: RecordWriter,
+        settings: Arc<FrameSettings>,
+    ) -> Self {
+        Self {
+            id,
+            reader,
+            writer,
+            settings,
+        }
+    }
+    pub fn framed<S>(id: ConnectionId, stream: S) -> Self
+    where
+        S: AsyncRead + AsyncWrite + Send + 'static,
+    {
+        let settings = Arc::new(FrameSettings::default());
+        let (reader, writer) = stream.split();
+        let reader = FramedRead::new(reader, codec::FrameCodec::new(settings.clone())).boxed();
+        let writer = FramedWrite::new(writer, codec::FrameCodec::new(settings.clone()));
+        Self::with_settings(id, reader, Box::pin(writer), settings)
+    }
+    pub fn id(&self) -> &ConnectionId {
+        &self.id
+    }
+    /// frames larger than this are rejected in both directions.
+    pub fn set_max_frame_size(&self, size: usize) {
+        self.settings.set_max_frame_size(size);
+    }
+}
+
+impl std::fmt::Debug for Connection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Connection({})", self.id)
+    }
+}
+
+#[async_trait]
+pub trait Listener: Send {
+    async fn accept(&mut self) -> io::Result<Connection>;
+}
+
+#[async_trait]
+pub trait Connector: Send + Sync {
+    async fn connect(&self) -> io::Result<Connection>;
+}
+
+// --
+
+cfg_server! {
+    use crate::config;
+
+    // the tls and websocket handshakes are in time, the same as the one of the records.
+    #[cfg(any(feature = "tls", feature = "websocket"))]
+    fn handshake_timeout(config: &config::Server) -> std::time::Duration {
+        std::time::Duration::from_millis(config.handshake_timeout_millisecond)
+    }
+
+    pub(crate) async fn bind(addr: &str, config: &config::Server) -> io::Result<Box<dyn Listener>> {
+        if let Some(path) = addr.strip_prefix("unix:") {
+            #[cfg(unix)]
+            return Ok(Box::new(unix::UnixListener::bind(path, config.unix_socket_mode).await?));
+            #[cfg(not(unix))]
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("unix socket isn't supported on this platform: {}", path),
+            ));
+        }
+        if addr.starts_with("ws://") {
+            #[cfg(feature = "websocket")]
+            return Ok(Box::new(ws::WsListener::bind(addr, handshake_timeout(config)).await?));
+            #[cfg(not(feature = "websocket"))]
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("the websocket feature is disabled: {}", addr),
+            ));
+        }
+        if let Some(data) = &config.tls {
+            #[cfg(feature = "tls")]
+            return Ok(Box::new(
+                tls::TlsListener::bind(addr, tls::acceptor(data)?, handshake_timeout(config)).await?,
+            ));
+            #[cfg(not(feature = "tls"))]
+            log::warn!("tls is configured for {}, but the tls feature is disabled. {:?}", addr, data);
+        }
+        Ok(Box::new(tcp::TcpListener::bind(addr).await?))
+    }
+}
+
+cfg_client! {
+    use crate::config::TlsClientData;
+
+    pub(crate) fn connector(
+        addr: &str,
+        tls: Option<&TlsClientData>,
+    ) -> io::Result<Arc<dyn Connector>> {
+        if let Some(path) = addr.strip_prefix("unix:") {
+            #[cfg(unix)]
+            return Ok(Arc::new(unix::UnixConnector::new(path)));
+            #[cfg(not(unix))]
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("unix socket isn't supported on this platform: {}", path),
+            ));
+        }
+        if addr.starts_with("ws://") {
+            #[cfg(feature = "websocket")]
+            return Ok(Arc::new(ws::WsConnector::new(addr)));
+            #[cfg(not(feature = "websocket"))]
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("the websocket feature is disabled: {}", addr),
+            ));
+        }
+        if let Some(data) = tls {
+            #[cfg(feature = "tls")]
+            return Ok(Arc::new(tls::TlsConnector::new(addr, tls::connector(data)?, &data.domain)));
+            #[cfg(not(feature = "tls"))]
+            log::warn!("tls is configured for {}, but the tls feature is disabled. {:?}", addr, data);
+        }
+        Ok(Arc::new(tcp::TcpConnector::new(addr)))
+    }
+}
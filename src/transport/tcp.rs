@@ -0,0 +1,48 @@
+// -- tcp.rs --
+
+use {
+    super::{Connection, Connector, Listener},
+    crate::servant::ConnectionId,
+    async_std::net,
+    async_trait::async_trait,
+    std::io,
+};
+
+// --
+
+cfg_server! {
+    pub(crate) struct TcpListener(net::TcpListener);
+
+    impl TcpListener {
+        pub(crate) async fn bind(addr: &str) -> io::Result<Self> {
+            Ok(Self(net::TcpListener::bind(addr).await?))
+        }
+    }
+
+    #[async_trait]
+    impl Listener for TcpListener {
+        async fn accept(&mut self) -> io::Result<Connection> {
+            let (stream, addr) = self.0.accept().await?;
+            Ok(Connection::framed(ConnectionId::Tcp(addr), stream))
+        }
+    }
+}
+
+cfg_client! {
+    pub(crate) struct TcpConnector(String);
+
+    impl TcpConnector {
+        pub(crate) fn new(addr: &str) -> Self {
+            Self(addr.to_string())
+        }
+    }
+
+    #[async_trait]
+    impl Connector for TcpConnector {
+        async fn connect(&self) -> io::Result<Connection> {
+            let stream = net::TcpStream::connect(&self.0).await?;
+            let local = stream.local_addr()?;
+            Ok(Connection::framed(ConnectionId::Tcp(local), stream))
+        }
+    }
+}
@@ -0,0 +1,245 @@
+// -- tls.rs --
+
+use {
+    super::Connection,
+    crate::servant::ConnectionId,
+    async_std::net,
+    async_trait::async_trait,
+    rustls::{internal::pemfile, Certificate, PrivateKey, RootCertStore},
+    std::{
+        fs::File,
+        io::{self, BufReader},
+    },
+};
+
+// --
+
+fn invalid_input(file_name: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("invalid pem file: {}", file_name),
+    )
+}
+
+fn load_certs(file_name: &str) -> io::Result<Vec<Certificate>> {
+    let mut reader = BufReader::new(File::open(file_name)?);
+    pemfile::certs(&mut reader).map_err(|_| invalid_input(file_name))
+}
+
+fn load_key(file_name: &str) -> io::Result<PrivateKey> {
+    let mut reader = BufReader::new(File::open(file_name)?);
+    let mut keys = pemfile::pkcs8_private_keys(&mut reader).map_err(|_| invalid_input(file_name))?;
+    if keys.is_empty() {
+        let mut reader = BufReader::new(File::open(file_name)?);
+        keys = pemfile::rsa_private_keys(&mut reader).map_err(|_| invalid_input(file_name))?;
+    }
+    keys.pop().ok_or_else(|| invalid_input(file_name))
+}
+
+fn load_roots(file_name: &str) -> io::Result<RootCertStore> {
+    let mut roots = RootCertStore::empty();
+    let mut reader = BufReader::new(File::open(file_name)?);
+    roots
+        .add_pem_file(&mut reader)
+        .map_err(|_| invalid_input(file_name))?;
+    Ok(roots)
+}
+
+// --
+
+cfg_server! {
+    use {
+        super::Listener,
+        crate::config::TlsServerData,
+        async_tls::TlsAcceptor,
+        futures::{
+            future::{BoxFuture, FutureExt as _},
+            select,
+            stream::{FuturesUnordered, StreamExt as _},
+        },
+        log::warn,
+        rustls::{AllowAnyAuthenticatedClient, NoClientAuth, ServerConfig},
+        std::{net::SocketAddr, sync::Arc, time::Duration},
+    };
+
+    pub(crate) fn acceptor(data: &TlsServerData) -> io::Result<TlsAcceptor> {
+        let verifier = match &data.client_ca {
+            Some(ca) => AllowAnyAuthenticatedClient::new(load_roots(ca)?),
+            None => NoClientAuth::new(),
+        };
+        let mut config = ServerConfig::new(verifier);
+        config
+            .set_single_cert(load_certs(&data.cert)?, load_key(&data.key)?)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
+        Ok(TlsAcceptor::from(Arc::new(config)))
+    }
+
+    // handshakes run concurrently in `pending`, so a slow peer doesn't block accepting.
+    // a peer which doesn't finish its handshake in time is dropped.
+    pub(crate) struct TlsListener {
+        listener: net::TcpListener,
+        acceptor: TlsAcceptor,
+        timeout: Duration,
+        pending: FuturesUnordered<BoxFuture<'static, (SocketAddr, io::Result<Connection>)>>,
+    }
+
+    impl TlsListener {
+        pub(crate) async fn bind(
+            addr: &str,
+            acceptor: TlsAcceptor,
+            timeout: Duration,
+        ) -> io::Result<Self> {
+            Ok(Self {
+                listener: net::TcpListener::bind(addr).await?,
+                acceptor,
+                timeout,
+                pending: FuturesUnordered::new(),
+            })
+        }
+    }
+
+    #[async_trait]
+    impl Listener for TlsListener {
+        async fn accept(&mut self) -> io::Result<Connection> {
+            enum SelectedValue {
+                Incoming(io::Result<(net::TcpStream, SocketAddr)>),
+                Handshake((SocketAddr, io::Result<Connection>)),
+            }
+            loop {
+                let value = select! {
+                    incoming = self.listener.accept().fuse() => SelectedValue::Incoming(incoming),
+                    handshake = self.pending.select_next_some() => SelectedValue::Handshake(handshake),
+                };
+                match value {
+                    SelectedValue::Incoming(incoming) => {
+                        let (stream, addr) = incoming?;
+                        let acceptor = self.acceptor.clone();
+                        let timeout = self.timeout;
+                        self.pending.push(
+                            async move {
+                                let r = async_std::future::timeout(timeout, acceptor.accept(stream))
+                                    .await
+                                    .unwrap_or_else(|_| {
+                                        Err(io::Error::new(io::ErrorKind::TimedOut, "timed out"))
+                                    })
+                                    .map(|s| Connection::framed(ConnectionId::Tcp(addr), s));
+                                (addr, r)
+                            }
+                            .boxed(),
+                        );
+                    }
+                    SelectedValue::Handshake((_, Ok(conn))) => return Ok(conn),
+                    SelectedValue::Handshake((addr, Err(e))) => {
+                        warn!("tls handshake with {} error({})", addr, e.to_string());
+                    }
+                }
+            }
+        }
+    }
+}
+
+cfg_client! {
+    use {
+        super::Connector,
+        crate::config::TlsClientData,
+        rustls::ClientConfig,
+    };
+
+    pub(crate) fn connector(data: &TlsClientData) -> io::Result<async_tls::TlsConnector> {
+        let mut config = ClientConfig::new();
+        config.root_store = load_roots(&data.ca)?;
+        if let (Some(cert), Some(key)) = (&data.cert, &data.key) {
+            config
+                .set_single_client_cert(load_certs(cert)?, load_key(key)?)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
+        }
+        Ok(async_tls::TlsConnector::from(std::sync::Arc::new(config)))
+    }
+
+    pub(crate) struct TlsConnector {
+        addr: String,
+        connector: async_tls::TlsConnector,
+        domain: String,
+    }
+
+    impl TlsConnector {
+        pub(crate) fn new(addr: &str, connector: async_tls::TlsConnector, domain: &str) -> Self {
+            Self {
+                addr: addr.to_string(),
+                connector,
+                domain: domain.to_string(),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl Connector for TlsConnector {
+        async fn connect(&self) -> io::Result<Connection> {
+            let stream = net::TcpStream::connect(&self.addr).await?;
+            let local = stream.local_addr()?;
+            let stream = self.connector.connect(&self.domain, stream).await?;
+            Ok(Connection::framed(ConnectionId::Tcp(local), stream))
+        }
+    }
+}
+
+// --
+
+#[cfg(all(test, feature = "server", feature = "client"))]
+mod tests {
+    use {
+        super::*,
+        crate::{
+            config::{TlsClientData, TlsServerData},
+            servant::Record,
+            transport::{Connector as _, Listener as _},
+        },
+        async_std::task,
+        futures::{sink::SinkExt as _, stream::StreamExt as _},
+        std::{fs, time::Duration},
+    };
+
+    #[test]
+    fn self_signed_test() {
+        let dir = std::env::temp_dir().join("servant_tls_test");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let cert_file = dir.join("cert.pem").to_str().unwrap().to_string();
+        let key_file = dir.join("key.pem").to_str().unwrap().to_string();
+        fs::write(&cert_file, cert.serialize_pem().unwrap()).unwrap();
+        fs::write(&key_file, cert.serialize_private_key_pem()).unwrap();
+        let server = TlsServerData {
+            cert: cert_file.clone(),
+            key: key_file,
+            client_ca: None,
+        };
+        let client = TlsClientData {
+            ca: cert_file,
+            domain: "localhost".to_string(),
+            cert: None,
+            key: None,
+        };
+
+        task::block_on(async {
+            let mut listener =
+                TlsListener::bind("127.0.0.1:0", acceptor(&server).unwrap(), Duration::from_secs(5))
+                    .await
+                    .unwrap();
+            let addr = listener.listener.local_addr().unwrap().to_string();
+            let connector = TlsConnector::new(&addr, connector(&client).unwrap(), &client.domain);
+            let terminal = task::spawn(async move {
+                let mut conn = connector.connect().await.unwrap();
+                conn.writer.send(Record::Ping { id: 1 }).await.unwrap();
+                conn.reader.next().await.unwrap().unwrap()
+            });
+            let mut conn = listener.accept().await.unwrap();
+            match conn.reader.next().await {
+                Some(Ok(Record::Ping { id })) => conn.writer.send(Record::Pong { id }).await.unwrap(),
+                r => panic!("unexpected {:?}", r),
+            }
+            assert!(matches!(terminal.await, Record::Pong { id: 1 }));
+        });
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
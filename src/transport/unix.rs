@@ -0,0 +1,157 @@
+// -- unix.rs --
+
+use {
+    super::{Connection, Connector, Listener},
+    crate::servant::ConnectionId,
+    async_std::os::unix::net,
+    async_trait::async_trait,
+    std::io,
+};
+
+// --
+
+cfg_server! {
+    use {
+        log::{info, warn},
+        std::{
+            fs,
+            os::unix::fs::{DirBuilderExt, FileTypeExt, PermissionsExt},
+            path::Path,
+        },
+    };
+
+    // only a socket left by a server which is gone is removed, anything else at the path
+    // is kept and the bind fails.
+    fn remove_stale(path: &str) -> io::Result<()> {
+        let meta = match fs::symlink_metadata(path) {
+            Ok(meta) => meta,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        if !meta.file_type().is_socket() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} exists and isn't a socket.", path),
+            ));
+        }
+        match std::os::unix::net::UnixStream::connect(path) {
+            Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => {
+                info!("stale socket {} is removed.", path);
+                fs::remove_file(path)
+            }
+            _ => Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                format!("{} is in use by another server.", path),
+            )),
+        }
+    }
+
+    // the socket is bound in a private directory and given its mode there, then moved
+    // to the path. nobody can connect to it before it has the mode.
+    async fn bind_with_mode(path: &str, mode: u32) -> io::Result<net::UnixListener> {
+        let dir = format!("{}.{}.tmp", path, std::process::id());
+        fs::DirBuilder::new().mode(0o700).create(&dir)?;
+        let bound = async {
+            let tmp = Path::new(&dir).join("socket");
+            let listener = net::UnixListener::bind(&tmp).await?;
+            fs::set_permissions(&tmp, fs::Permissions::from_mode(mode))?;
+            fs::rename(&tmp, path)?;
+            Ok(listener)
+        }
+        .await;
+        fs::remove_dir_all(&dir).unwrap_or_else(|e| warn!("{}", e.to_string()));
+        bound
+    }
+
+    pub(crate) struct UnixListener {
+        listener: net::UnixListener,
+        path: String,
+        seq: usize,
+    }
+
+    impl UnixListener {
+        pub(crate) async fn bind(path: &str, mode: Option<u32>) -> io::Result<Self> {
+            remove_stale(path)?;
+            let listener = match mode {
+                Some(mode) => bind_with_mode(path, mode).await?,
+                None => net::UnixListener::bind(path).await?,
+            };
+            Ok(Self {
+                listener,
+                path: path.to_string(),
+                seq: 0,
+            })
+        }
+    }
+
+    impl Drop for UnixListener {
+        fn drop(&mut self) {
+            fs::remove_file(&self.path).unwrap_or_else(|e| warn!("{}", e.to_string()));
+        }
+    }
+
+    #[async_trait]
+    impl Listener for UnixListener {
+        async fn accept(&mut self) -> io::Result<Connection> {
+            let (stream, _) = self.listener.accept().await?;
+            self.seq += 1;
+            Ok(Connection::framed(
+                ConnectionId::Unix(self.path.clone(), self.seq),
+                stream,
+            ))
+        }
+    }
+}
+
+cfg_client! {
+    pub(crate) struct UnixConnector(String);
+
+    impl UnixConnector {
+        pub(crate) fn new(path: &str) -> Self {
+            Self(path.to_string())
+        }
+    }
+
+    #[async_trait]
+    impl Connector for UnixConnector {
+        async fn connect(&self) -> io::Result<Connection> {
+            let stream = net::UnixStream::connect(&self.0).await?;
+            Ok(Connection::framed(ConnectionId::Unix(self.0.clone(), 0), stream))
+        }
+    }
+}
+
+// --
+
+#[cfg(all(test, feature = "server"))]
+mod tests {
+    use {super::*, async_std::task, std::fs};
+
+    #[test]
+    fn bind_test() {
+        let dir = std::env::temp_dir().join("servant_unix_test");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("a.sock").to_str().unwrap().to_string();
+        task::block_on(async {
+            // a regular file is kept.
+            fs::write(&path, b"data").unwrap();
+            assert!(UnixListener::bind(&path, None).await.is_err());
+            assert_eq!(fs::read(&path).unwrap(), b"data");
+            fs::remove_file(&path).unwrap();
+
+            // a live socket is kept, a stale one is replaced.
+            let listener = UnixListener::bind(&path, Some(0o600)).await.unwrap();
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+            let err = UnixListener::bind(&path, None).await.err().unwrap();
+            assert_eq!(err.kind(), io::ErrorKind::AddrInUse);
+            let stale = std::os::unix::net::UnixListener::bind(dir.join("b.sock")).unwrap();
+            drop(stale);
+            let stale = dir.join("b.sock").to_str().unwrap().to_string();
+            assert!(UnixListener::bind(&stale, None).await.is_ok());
+            drop(listener);
+        });
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,154 @@
+// -- ws.rs --
+
+use {
+    super::{Connection, FrameSettings, RecordReader, RecordWriter},
+    crate::servant::{ConnectionId, Record},
+    async_std::net,
+    async_trait::async_trait,
+    async_tungstenite::{tungstenite::Message, WebSocketStream},
+    futures::{
+        future,
+        io::{AsyncRead, AsyncWrite},
+        sink::SinkExt as _,
+        stream::StreamExt as _,
+    },
+    log::warn,
+    std::{io, sync::Arc},
+};
+
+// --
+
+fn to_io_error<E: ToString>(e: E) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, e.to_string())
+}
+
+// every Record is carried in one binary message, other messages are skipped.
+// the format of the connection, json as well, is always in binary messages.
+fn connection<S>(id: ConnectionId, ws: WebSocketStream<S>) -> Connection
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let settings = Arc::new(FrameSettings::default());
+    let (writer, reader) = ws.split();
+    let limit = settings.clone();
+    let reader: RecordReader = reader
+        .filter_map(move |m| {
+            future::ready(match m {
+                Ok(Message::Binary(buf)) => Some(limit.decode(&buf)),
+                Ok(Message::Text(text)) => {
+                    warn!("websocket text message is skipped: {}", text);
+                    None
+                }
+                Ok(_) => None,
+                Err(e) => Some(Err(to_io_error(e))),
+            })
+        })
+        .boxed();
+    let limit = settings.clone();
+    let writer: RecordWriter = Box::pin(writer.sink_map_err(to_io_error).with(move |r: Record| {
+        future::ready(limit.encode(r).map(Message::Binary))
+    }));
+    Connection::with_settings(id, reader, writer, settings)
+}
+
+// "ws://127.0.0.1:9001/path" -> "127.0.0.1:9001"
+fn host_of(url: &str) -> &str {
+    let s = url.strip_prefix("ws://").unwrap_or(url);
+    s.split('/').next().unwrap_or(s)
+}
+
+// --
+
+cfg_server! {
+    use {
+        super::Listener,
+        futures::{
+            future::{BoxFuture, FutureExt as _},
+            select,
+            stream::FuturesUnordered,
+        },
+        std::{net::SocketAddr, time::Duration},
+    };
+
+    // websocket handshakes run concurrently in `pending` and in time, the same as tls.
+    pub(crate) struct WsListener {
+        listener: net::TcpListener,
+        timeout: Duration,
+        pending: FuturesUnordered<BoxFuture<'static, (SocketAddr, io::Result<Connection>)>>,
+    }
+
+    impl WsListener {
+        pub(crate) async fn bind(url: &str, timeout: Duration) -> io::Result<Self> {
+            Ok(Self {
+                listener: net::TcpListener::bind(host_of(url)).await?,
+                timeout,
+                pending: FuturesUnordered::new(),
+            })
+        }
+    }
+
+    #[async_trait]
+    impl Listener for WsListener {
+        async fn accept(&mut self) -> io::Result<Connection> {
+            enum SelectedValue {
+                Incoming(io::Result<(net::TcpStream, SocketAddr)>),
+                Handshake((SocketAddr, io::Result<Connection>)),
+            }
+            loop {
+                let value = select! {
+                    incoming = self.listener.accept().fuse() => SelectedValue::Incoming(incoming),
+                    handshake = self.pending.select_next_some() => SelectedValue::Handshake(handshake),
+                };
+                match value {
+                    SelectedValue::Incoming(incoming) => {
+                        let (stream, addr) = incoming?;
+                        let timeout = self.timeout;
+                        self.pending.push(
+                            async move {
+                                let accept = async_tungstenite::accept_async(stream);
+                                let r = match async_std::future::timeout(timeout, accept).await {
+                                    Ok(r) => r
+                                        .map(|ws| connection(ConnectionId::WebSocket(addr), ws))
+                                        .map_err(to_io_error),
+                                    Err(_) => {
+                                        Err(io::Error::new(io::ErrorKind::TimedOut, "timed out"))
+                                    }
+                                };
+                                (addr, r)
+                            }
+                            .boxed(),
+                        );
+                    }
+                    SelectedValue::Handshake((_, Ok(conn))) => return Ok(conn),
+                    SelectedValue::Handshake((addr, Err(e))) => {
+                        warn!("websocket handshake with {} error({})", addr, e.to_string());
+                    }
+                }
+            }
+        }
+    }
+}
+
+cfg_client! {
+    use super::Connector;
+
+    pub(crate) struct WsConnector(String);
+
+    impl WsConnector {
+        pub(crate) fn new(url: &str) -> Self {
+            Self(url.to_string())
+        }
+    }
+
+    #[async_trait]
+    impl Connector for WsConnector {
+        async fn connect(&self) -> io::Result<Connection> {
+            let stream = net::TcpStream::connect(host_of(&self.0)).await?;
+            let local = stream.local_addr()?;
+            let (ws, _response) = async_tungstenite::client_async(self.0.as_str(), stream)
+                .await
+                .map_err(to_io_error)?;
+            Ok(connection(ConnectionId::WebSocket(local), ws))
+        }
+    }
+}